@@ -0,0 +1,121 @@
+//! Rewrite engine of the policy, exposed to native tooling, e.g. CI checks
+//! or a CLI, so that they rewrite images exactly like the admission policy.
+
+use kubewarden_policy_sdk::settings::Validatable;
+
+use crate::mutation::{Mutation, DEFAULT_OS};
+use crate::settings::{Destination, Rule, Settings};
+
+/// Images rewriter configured with the settings of the policy
+pub struct RewriteEngine {
+    settings: Settings,
+}
+
+impl RewriteEngine {
+    /// Creates an engine from the JSON settings of the policy, e.g. the
+    /// `settings` of a ClusterAdmissionPolicy. The checks backed by host
+    /// capabilities, looking up or verifying the images at the mirror, or at
+    /// the `failover` destinations, and `mappingsConfigMap` are not available
+    /// outside of the policy server and are ignored.
+    pub fn new(settings: serde_json::Value) -> Result<Self, String> {
+        let mut settings: Settings =
+            serde_json::from_value(settings).map_err(|e| format!("invalid settings: {}", e))?;
+        settings.validate()?;
+        settings.request_replication = false;
+        settings.pin_digest = false;
+        settings.verify_destination_exists = false;
+        settings.signatures.clear();
+        settings.mappings_artifact = None;
+        settings.mappings_config_map = None;
+        let destinations = settings
+            .repos
+            .values_mut()
+            .chain(settings.baseline.values_mut())
+            .chain(settings.orgs.values_mut());
+        for destination in destinations {
+            if let Destination::Conditional(target) = destination {
+                target.failover.clear();
+            }
+        }
+        for rule in &mut settings.rules {
+            if let Rule::Prefix { target, .. } = rule {
+                target.failover.clear();
+            }
+        }
+        Ok(RewriteEngine { settings })
+    }
+
+    /// Returns the image to pull in place of `image`, if it is rewritten.
+    /// Images the policy rejects are not rewritten either, see `check`.
+    pub fn rewrite(&self, image: &str) -> Option<String> {
+        self.check(image).ok().flatten()
+    }
+
+    /// Returns the image to pull in place of `image`, if it is rewritten, or
+    /// the reason why the policy rejects it, e.g. with `rejectOnNoMatch`
+    pub fn check(&self, image: &str) -> Result<Option<String>, String> {
+        Mutation::new(&self.settings, DEFAULT_OS.to_string())
+            .mutate_image("", image)
+            .map_err(|finding| finding.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrite_images() -> Result<(), ()> {
+        let engine = RewriteEngine::new(serde_json::json!({
+            "repos": {"docker.io": "mirror.corp/dockerhub"},
+            "rules": [{"source": "quay.io", "destination": "mirror.corp/quay"}],
+            "rejectOnNoMatch": true,
+            "passthroughRegistries": ["registry.corp"]
+        }))
+        .unwrap();
+
+        assert_eq!(
+            engine.rewrite("nginx:1.25").as_deref(),
+            Some("mirror.corp/dockerhub/library/nginx:1.25")
+        );
+        assert_eq!(
+            engine.rewrite("quay.io/foo/bar:v1").as_deref(),
+            Some("mirror.corp/quay/foo/bar:v1")
+        );
+        assert_eq!(engine.check("registry.corp/app:v1"), Ok(None));
+        assert!(engine.check("ghcr.io/foo/bar").is_err());
+        assert_eq!(engine.rewrite("ghcr.io/foo/bar"), None);
+
+        assert!(RewriteEngine::new(serde_json::json!({"repos": {"docker.io": ""}})).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn failover_is_ignored() -> Result<(), ()> {
+        let engine = RewriteEngine::new(serde_json::json!({
+            "repos": {"docker.io": {
+                "destination": "mirror.corp/dockerhub",
+                "failover": ["backup.corp/dockerhub"]
+            }},
+            "rules": [{
+                "source": "quay.io",
+                "destination": "mirror.corp/quay",
+                "failover": ["backup.corp/quay"]
+            }],
+            "onHostError": "failClosed"
+        }))
+        .unwrap();
+
+        assert_eq!(
+            engine.check("nginx:1.25"),
+            Ok(Some("mirror.corp/dockerhub/library/nginx:1.25".to_string()))
+        );
+        assert_eq!(
+            engine.check("quay.io/foo/bar:v1"),
+            Ok(Some("mirror.corp/quay/foo/bar:v1".to_string()))
+        );
+
+        Ok(())
+    }
+}
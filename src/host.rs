@@ -0,0 +1,280 @@
+//! Abstraction over the host capabilities offered by the policy server, so
+//! that the policy can be evaluated against an in-memory mock in tests.
+
+use std::fmt;
+
+use k8s_openapi::api::core::v1 as apicore;
+use kubewarden::host_capabilities::{oci, verification};
+use kubewarden::wapc_guest;
+use serde::Serialize;
+
+use crate::settings::Signature;
+
+#[cfg(test)]
+use std::{cell::RefCell, collections::hash_map::HashMap};
+
+/// Error of a host capability call
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum HostError {
+    /// The requested object does not exist, e.g. an unknown image manifest
+    NotFound(String),
+    /// The host capability could not be evaluated, e.g. the registry is down
+    Unavailable(String),
+}
+
+impl fmt::Display for HostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostError::NotFound(e) => write!(f, "not found: {}", e),
+            HostError::Unavailable(e) => write!(f, "host capability unavailable: {}", e),
+        }
+    }
+}
+
+/// Fragments of the errors reported by registries for unknown objects
+const NOT_FOUND_ERRORS: &[&str] = &[
+    "manifest unknown",
+    "MANIFEST_UNKNOWN",
+    "NAME_UNKNOWN",
+    "not found",
+    "404",
+];
+
+impl HostError {
+    /// Classifies the error message of a host capability call
+    fn from_message(message: String) -> Self {
+        if NOT_FOUND_ERRORS.iter().any(|e| message.contains(e)) {
+            HostError::NotFound(message)
+        } else {
+            HostError::Unavailable(message)
+        }
+    }
+}
+
+/// Host capabilities used by the policy
+pub(crate) trait HostCapabilities {
+    /// Returns the digest of the manifest of `image`
+    fn manifest_digest(&self, image: &str) -> Result<String, HostError>;
+
+    /// Determines if `image` carries the sigstore `signature`
+    fn verify_signature(&self, image: &str, signature: &Signature) -> Result<bool, HostError>;
+
+    /// Returns the manifest of `image`, e.g. to read its annotations
+    fn oci_manifest(&self, image: &str) -> Result<serde_json::Value, HostError>;
+
+    /// Returns the ConfigMap `name` of `namespace`
+    fn config_map(&self, namespace: &str, name: &str) -> Result<apicore::ConfigMap, HostError>;
+}
+
+/// Request of the `get_resource` operation of the Kubernetes host capability
+#[derive(Serialize)]
+struct GetResourceRequest<'a> {
+    api_version: &'a str,
+    kind: &'a str,
+    name: &'a str,
+    namespace: Option<&'a str>,
+    /// Leave the policy server caching the resource, refreshed by its
+    /// watch of the cluster
+    disable_cache: bool,
+}
+
+/// Host capabilities of the policy server, invoked through waPC
+pub(crate) struct WapcHost;
+
+impl HostCapabilities for WapcHost {
+    fn manifest_digest(&self, image: &str) -> Result<String, HostError> {
+        oci::manifest_digest(image)
+            .map(|response| response.digest)
+            .map_err(|e| HostError::from_message(format!("{:#}", e)))
+    }
+
+    fn verify_signature(&self, image: &str, signature: &Signature) -> Result<bool, HostError> {
+        let response = match signature.clone() {
+            Signature::PubKeys {
+                pub_keys,
+                annotations,
+                ..
+            } => verification::verify_pub_keys_image(image, pub_keys, annotations),
+            Signature::Keyless {
+                keyless,
+                annotations,
+                ..
+            } => verification::verify_keyless_exact_match(
+                image,
+                keyless
+                    .into_iter()
+                    .map(|k| verification::KeylessInfo {
+                        issuer: k.issuer,
+                        subject: k.subject,
+                    })
+                    .collect(),
+                annotations,
+            ),
+            Signature::KeylessPrefix {
+                keyless_prefix,
+                annotations,
+                ..
+            } => verification::verify_keyless_prefix_match(
+                image,
+                keyless_prefix
+                    .into_iter()
+                    .map(|k| verification::KeylessPrefixInfo {
+                        issuer: k.issuer,
+                        url_prefix: k.url_prefix,
+                    })
+                    .collect(),
+                annotations,
+            ),
+            Signature::GithubActions {
+                github_actions,
+                annotations,
+                ..
+            } => verification::verify_keyless_github_actions(
+                image,
+                github_actions.owner,
+                github_actions.repo,
+                annotations,
+            ),
+        };
+        response
+            .map(|response| response.is_trusted)
+            .map_err(|e| HostError::from_message(format!("{:#}", e)))
+    }
+
+    fn oci_manifest(&self, image: &str) -> Result<serde_json::Value, HostError> {
+        let request =
+            serde_json::to_vec(image).map_err(|e| HostError::Unavailable(e.to_string()))?;
+        let response = wapc_guest::host_call("kubewarden", "oci", "v1/oci_manifest", &request)
+            .map_err(|e| HostError::from_message(format!("{:?}", e)))?;
+        serde_json::from_slice(&response).map_err(|e| HostError::Unavailable(e.to_string()))
+    }
+
+    fn config_map(&self, namespace: &str, name: &str) -> Result<apicore::ConfigMap, HostError> {
+        let request = serde_json::to_vec(&GetResourceRequest {
+            api_version: "v1",
+            kind: "ConfigMap",
+            name,
+            namespace: Some(namespace),
+            disable_cache: false,
+        })
+        .map_err(|e| HostError::Unavailable(e.to_string()))?;
+        let response = wapc_guest::host_call("kubewarden", "kubernetes", "get_resource", &request)
+            .map_err(|e| HostError::from_message(format!("{:?}", e)))?;
+        serde_json::from_slice(&response).map_err(|e| HostError::Unavailable(e.to_string()))
+    }
+}
+
+/// In-memory host capabilities, installed with [`mock`]
+#[cfg(test)]
+#[derive(Default, Clone)]
+pub(crate) struct MockHost {
+    /// Digests of the images known to the mocked registries, any other image
+    /// is reported as missing
+    pub digests: HashMap<String, String>,
+    /// Error returned by every call, simulating an unavailable host
+    pub unavailable: Option<String>,
+    /// Images carrying every required signature
+    pub signed: Vec<String>,
+    /// Manifests of the images known to the mocked registries
+    pub manifests: HashMap<String, serde_json::Value>,
+    /// ConfigMaps of the mocked cluster, by `namespace/name`
+    pub config_maps: HashMap<String, apicore::ConfigMap>,
+}
+
+#[cfg(test)]
+impl HostCapabilities for MockHost {
+    fn manifest_digest(&self, image: &str) -> Result<String, HostError> {
+        if let Some(e) = &self.unavailable {
+            return Err(HostError::Unavailable(e.clone()));
+        }
+        self.digests
+            .get(image)
+            .cloned()
+            .ok_or_else(|| HostError::NotFound(format!("manifest unknown: {}", image)))
+    }
+
+    fn verify_signature(&self, image: &str, _signature: &Signature) -> Result<bool, HostError> {
+        if let Some(e) = &self.unavailable {
+            return Err(HostError::Unavailable(e.clone()));
+        }
+        Ok(self.signed.iter().any(|signed| signed == image))
+    }
+
+    fn oci_manifest(&self, image: &str) -> Result<serde_json::Value, HostError> {
+        if let Some(e) = &self.unavailable {
+            return Err(HostError::Unavailable(e.clone()));
+        }
+        self.manifests
+            .get(image)
+            .cloned()
+            .ok_or_else(|| HostError::NotFound(format!("manifest unknown: {}", image)))
+    }
+
+    fn config_map(&self, namespace: &str, name: &str) -> Result<apicore::ConfigMap, HostError> {
+        if let Some(e) = &self.unavailable {
+            return Err(HostError::Unavailable(e.clone()));
+        }
+        let key = format!("{}/{}", namespace, name);
+        self.config_maps
+            .get(&key)
+            .cloned()
+            .ok_or_else(|| HostError::NotFound(format!("configmaps \"{}\" not found", name)))
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    static MOCK_HOST: RefCell<Option<MockHost>> = const { RefCell::new(None) };
+}
+
+/// Replaces the host capabilities with `host` for the rest of the current
+/// test, including evaluations through the `Testcase` harness
+#[cfg(test)]
+pub(crate) fn mock(host: MockHost) {
+    MOCK_HOST.with(|mock| *mock.borrow_mut() = Some(host));
+}
+
+/// Runs `f` with the host capabilities in use
+pub(crate) fn with_host<T>(f: impl FnOnce(&dyn HostCapabilities) -> T) -> T {
+    #[cfg(test)]
+    if let Some(host) = MOCK_HOST.with(|mock| mock.borrow().clone()) {
+        return f(&host);
+    }
+    f(&WapcHost)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_host() {
+        // without a mock, the waPC host calls fail outside of a policy server
+        assert!(with_host(|host| host.manifest_digest("busybox").is_err()));
+
+        mock(MockHost {
+            digests: HashMap::from([("busybox".to_string(), "sha256:1234".to_string())]),
+            ..Default::default()
+        });
+        assert_eq!(
+            with_host(|host| host.manifest_digest("busybox")),
+            Ok("sha256:1234".to_string())
+        );
+        assert!(matches!(
+            with_host(|host| host.manifest_digest("alpine")),
+            Err(HostError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn classify_host_errors() {
+        assert_eq!(
+            HostError::from_message("MANIFEST_UNKNOWN: manifest unknown".to_string()),
+            HostError::NotFound("MANIFEST_UNKNOWN: manifest unknown".to_string())
+        );
+        assert_eq!(
+            HostError::from_message("connection refused".to_string()),
+            HostError::Unavailable("connection refused".to_string())
+        );
+    }
+}
@@ -1,9 +1,14 @@
+use std::collections::HashMap;
 use std::fmt;
+use std::str::FromStr;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ImageRef {
-    /// an optional registry, generally Docker Hub if unset
-    pub registry: Option<String>,
+    /// an optional registry hostname, generally Docker Hub if unset
+    pub host: Option<String>,
+
+    /// an optional registry port, e.g. `5000` for `localhost:5000`
+    pub port: Option<u16>,
 
     /// an image string, possibly including a user or organization name
     pub image: String,
@@ -12,69 +17,476 @@ pub struct ImageRef {
     /// to mean `:latest` if unset
     pub tag: Option<String>,
 
-    /// An optional embedded image hash, e.g. `sha256:...`. Conflicts with `tag`.
+    /// An optional embedded image hash, e.g. `sha256:...`. When set along
+    /// with `tag`, e.g. `nginx:1.25@sha256:...`, the hash is what is pulled.
     pub hash: Option<String>,
 }
 
-/// Determines if an ImageRef token refers to a registry hostname or not
+/// Reason why an image reference cannot be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageParseError {
+    /// The reference is empty
+    Empty,
+    /// The registry is not a valid hostname, with an optional port
+    InvalidRegistry(String),
+    /// A component of the repository path is not lowercase alphanumerics,
+    /// optionally separated by `.`, `_`, `__` or dashes
+    InvalidRepository(String),
+    /// The tag is not made of at most 128 word characters, dots and dashes,
+    /// not starting with a dot or a dash
+    InvalidTag(String),
+    /// The digest is not an `algorithm:encoded` pair
+    InvalidDigest(String),
+    /// The name, registry included, is longer than 255 characters
+    NameTooLong(String),
+}
+
+impl fmt::Display for ImageParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageParseError::Empty => write!(f, "empty image reference"),
+            ImageParseError::InvalidRegistry(r) => write!(f, "invalid registry {:?}", r),
+            ImageParseError::InvalidRepository(r) => {
+                write!(f, "invalid repository path component {:?}", r)
+            }
+            ImageParseError::InvalidTag(t) => write!(f, "invalid tag {:?}", t),
+            ImageParseError::InvalidDigest(d) => write!(f, "invalid digest {:?}", d),
+            ImageParseError::NameTooLong(n) => {
+                write!(f, "name {:?} longer than {} characters", n, MAX_NAME_LENGTH)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ImageParseError {}
+
+/// Determines if `registry` is a hostname made of alphanumerics and dashes,
+/// or a bracketed IPv6 address, with an optional port
+pub fn is_valid_registry(registry: &str) -> bool {
+    let (host, port) = match registry.rsplit_once(':') {
+        Some((host, port)) if !port.ends_with(']') => (host, Some(port)),
+        _ => (registry, None),
+    };
+    if let Some(address) = host.strip_prefix('[') {
+        return address
+            .strip_suffix(']')
+            .is_some_and(|address| address.parse::<std::net::Ipv6Addr>().is_ok())
+            && port.is_none_or(|port| port.parse::<u16>().is_ok());
+    }
+    port.is_none_or(|port| !port.is_empty() && port.chars().all(|c| c.is_ascii_digit()))
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Determines if `component` is a valid component of a repository path
+fn is_valid_path_component(component: &str) -> bool {
+    let alnum = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit();
+    let mut separator = String::new();
+    for c in component.chars() {
+        if alnum(c) {
+            if !matches!(separator.as_str(), "" | "." | "_" | "__")
+                && !separator.chars().all(|c| c == '-')
+            {
+                return false;
+            }
+            separator.clear();
+        } else if matches!(c, '.' | '_' | '-') {
+            separator.push(c);
+        } else {
+            return false;
+        }
+    }
+    component.starts_with(alnum) && separator.is_empty()
+}
+
+/// Determines if `tag` is a valid tag
+fn is_valid_tag(tag: &str) -> bool {
+    let word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    tag.len() <= 128
+        && tag.starts_with(word)
+        && tag.chars().all(|c| word(c) || c == '.' || c == '-')
+}
+
+/// Determines if `digest` is a valid `algorithm:encoded` pair
+fn is_valid_digest(digest: &str) -> bool {
+    digest.split_once(':').is_some_and(|(algorithm, encoded)| {
+        algorithm.starts_with(|c: char| c.is_ascii_alphabetic())
+            && algorithm
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '+' | '.'))
+            && !encoded.is_empty()
+            && encoded
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '=' | '_' | '-'))
+    })
+}
+
+/// Maximum length of the name of an image, registry included
+const MAX_NAME_LENGTH: usize = 255;
+
+/// Encoded lengths of the digests of the registered algorithms
+const DIGEST_LENGTHS: &[(&str, usize)] = &[("sha256", 64), ("sha512", 128)];
+
+/// Determines if `digest` is a valid `algorithm:encoded` pair in the OCI
+/// grammar: lowercase algorithm components, joined by single separators, and
+/// the lowercase hex encoding of the registered algorithms
+fn is_canonical_digest(digest: &str) -> bool {
+    let Some((algorithm, encoded)) = digest.split_once(':') else {
+        return false;
+    };
+    let lowercase = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit();
+    let components_valid = algorithm
+        .split(['+', '.', '_', '-'])
+        .all(|component| !component.is_empty() && component.chars().all(lowercase));
+    let length = DIGEST_LENGTHS
+        .iter()
+        .find(|(registered, _)| *registered == algorithm)
+        .map(|(_, length)| *length);
+    components_valid
+        && is_valid_digest(digest)
+        && length.is_none_or(|length| {
+            encoded.len() == length
+                && encoded
+                    .chars()
+                    .all(|c| c.is_ascii_digit() || matches!(c, 'a'..='f'))
+        })
+}
+
+/// Determines if an ImageRef token refers to a registry hostname or not,
+/// including bracketed IPv6 addresses, e.g. `[::1]:5000`
 ///
 /// Based on rules from https://stackoverflow.com/a/42116190
 fn is_registry(token: &str) -> bool {
-    token == "localhost" || token.contains('.') || token.contains(':')
+    token == "localhost" || token.contains('.') || token.contains(':') || token.starts_with('[')
+}
+
+/// The canonical hostname of the Kubernetes community registry
+const K8S_REGISTRY: &str = "registry.k8s.io";
+
+/// Legacy hostnames serving the same content as `registry.k8s.io`
+const LEGACY_K8S_REGISTRIES: &[&str] = &[
+    "k8s.gcr.io",
+    "us.k8s.gcr.io",
+    "eu.k8s.gcr.io",
+    "asia.k8s.gcr.io",
+];
+
+/// Replaces a legacy Kubernetes registry hostname at the start of an image
+/// string or prefix with `registry.k8s.io`
+pub fn unify_k8s_registry(s: &str) -> String {
+    let (host, rest) = s.split_at(s.find('/').unwrap_or(s.len()));
+    if LEGACY_K8S_REGISTRIES.contains(&host) {
+        format!("{}{}", K8S_REGISTRY, rest)
+    } else {
+        s.to_string()
+    }
+}
+
+/// Hostnames serving the same content as `docker.io`
+pub const DOCKER_HUB_ALIASES: &[&str] = &[
+    "index.docker.io",
+    "registry-1.docker.io",
+    "registry.hub.docker.com",
+];
+
+/// Replaces the registry at the start of an image string or prefix with the
+/// registry it is an alias of, according to `aliases`
+pub fn unalias_registry(s: &str, aliases: &HashMap<String, String>) -> String {
+    let (registry, rest) = s.split_at(s.find('/').unwrap_or(s.len()));
+    match aliases.get(registry) {
+        Some(canonical) => format!("{}{}", canonical, rest),
+        None => s.to_string(),
+    }
+}
+
+/// Determines if an image string names its registry, rather than relying on
+/// the implicit Docker Hub, e.g. `quay.io/foo/bar` but not `alpine:3.19`
+pub fn has_registry(s: &str) -> bool {
+    s.split_once('/')
+        .is_some_and(|(first, _)| is_registry(first))
+}
+
+/// Determines if an image string carries an explicit tag or digest, rather
+/// than relying on the implicit `latest` tag
+pub fn has_explicit_tag(s: &str) -> bool {
+    let name = s.rsplit('/').next().unwrap_or(s);
+    name.contains(':') || name.contains('@')
+}
+
+/// Determines if a pattern of images is a whole reference, with a
+/// repository path and a tag or digest, rather than a prefix or a glob
+pub fn is_whole_reference(pattern: &str) -> bool {
+    pattern.contains('/') && has_explicit_tag(pattern)
+}
+
+/// Determines if an image string still contains an unrendered template
+/// placeholder, such as `{{ .Values.image }}` or `${IMAGE}`
+pub fn has_template_placeholder(s: &str) -> bool {
+    let closed_after = |open: &str, close: &str| {
+        s.find(open)
+            .is_some_and(|pos| s[pos + open.len()..].contains(close))
+    };
+    closed_after("{{", "}}") || closed_after("${", "}")
+}
+
+/// Matches a single component against a pattern, where `*` matches any
+/// sequence of characters within the component
+pub fn wildcard_match(pattern: &str, component: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == component,
+        Some((head, rest)) => match component.strip_prefix(head) {
+            Some(tail) => (0..=tail.len())
+                .filter(|i| tail.is_char_boundary(*i))
+                .any(|i| wildcard_match(rest, &tail[i..])),
+            None => false,
+        },
+    }
+}
+
+/// Determines if images under the repository `prefix`, e.g. a destination,
+/// can match the rule `source`, their common leading components matching
+pub fn source_overlaps(source: &str, prefix: &str) -> bool {
+    source
+        .trim_end_matches('/')
+        .split('/')
+        .zip(prefix.split('/'))
+        .all(|(pattern, component)| wildcard_match(pattern, component))
+}
+
+/// Splits a registry into its hostname and optional port. IPv6 hostnames
+/// keep their brackets, e.g. `[::1]` for `[::1]:5000`.
+fn split_registry(registry: &str) -> (String, Option<u16>) {
+    match registry.rsplit_once(':') {
+        // the colons of a bracketed IPv6 address without port
+        Some((_, port)) if port.ends_with(']') => (registry.to_string(), None),
+        Some((host, port)) => match port.parse() {
+            Ok(port) => (host.to_string(), Some(port)),
+            Err(_) => (registry.to_string(), None),
+        },
+        None => (registry.to_string(), None),
+    }
 }
 
 impl ImageRef {
+    /// Returns the registry hostname
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+
+    /// Returns the registry port, if any
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    /// Returns the registry, including the port if any, e.g. `localhost:5000`
+    pub fn registry(&self) -> Option<String> {
+        self.host.as_ref().map(|host| match self.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.clone(),
+        })
+    }
+
+    /// Returns the repository, that is the registry and the repository path
+    /// without tag nor digest, e.g. `docker.io/library/alpine`
+    pub fn repository(&self) -> String {
+        match self.registry() {
+            Some(registry) => format!("{}/{}", registry, self.image),
+            None => self.image.clone(),
+        }
+    }
+
+    /// Returns the number of components in the repository path, e.g. `2` for
+    /// `library/alpine`
+    pub fn path_depth(&self) -> usize {
+        self.image.split('/').count()
+    }
+
+    /// Returns the organization owning the repository, that is the first
+    /// component of a repository path with more than one component
+    pub fn org(&self) -> Option<&str> {
+        self.image.split_once('/').map(|(org, _)| org)
+    }
+
+    /// Returns the repository path without the organization
+    pub fn path_in_org(&self) -> &str {
+        self.image
+            .split_once('/')
+            .map_or(self.image.as_str(), |(_, path)| path)
+    }
+
+    /// Returns the components of the repository: the registry, followed by
+    /// the components of the repository path
+    pub fn components(&self) -> Vec<String> {
+        self.registry()
+            .into_iter()
+            .chain(self.image.split('/').map(String::from))
+            .collect()
+    }
+
+    /// Matches a rule source against the leading components of the
+    /// repository, so that `docker.io/foo` matches `docker.io/foo/app` but
+    /// not `docker.io/foobar/app`. The source can be a glob, where `*` never
+    /// crosses a `/` boundary, e.g. `*.gcr.io` matches any image of
+    /// `us.gcr.io` and `docker.io/bitnami/*` any image of the `bitnami`
+    /// organization. Returns the number of matched components to replace
+    /// when rewriting, trailing `*` components only selecting the images and
+    /// being kept.
+    pub fn match_source(&self, source: &str) -> Option<usize> {
+        let components = self.components();
+        let patterns: Vec<&str> = source.trim_end_matches('/').split('/').collect();
+        if patterns.len() > components.len()
+            || !patterns
+                .iter()
+                .zip(&components)
+                .all(|(pattern, component)| wildcard_match(pattern, component))
+        {
+            return None;
+        }
+        let kept = patterns.iter().rev().take_while(|p| **p == "*").count();
+        Some(patterns.len() - kept)
+    }
+
+    /// Returns the image with its `skip` leading components replaced by
+    /// `prefix`, keeping the tag and digest
+    pub fn rebase(&self, skip: usize, prefix: &str) -> String {
+        let rest = self.components()[skip..].join("/");
+        let mut rebased = if rest.is_empty() {
+            prefix.to_string()
+        } else {
+            format!("{}/{}", prefix, rest)
+        };
+        if let Some(tag) = &self.tag {
+            rebased.push_str(&format!(":{}", tag));
+        }
+        if let Some(hash) = &self.hash {
+            rebased.push_str(&format!("@{}", hash));
+        }
+        rebased
+    }
+
+    /// Determines if the image is a Docker Hub official image
+    pub fn is_official(&self) -> bool {
+        self.host() == Some("docker.io") && self.image.starts_with("library/")
+    }
+
+    /// Parses an `ImageRef` from a string, checking every component of the
+    /// reference
+    pub fn try_parse(s: &str) -> Result<ImageRef, ImageParseError> {
+        if s.is_empty() {
+            return Err(ImageParseError::Empty);
+        }
+        let (name, digest) = match s.split_once('@') {
+            Some((name, digest)) => (name, Some(digest)),
+            None => (s, None),
+        };
+        if let Some(digest) = digest {
+            if !is_valid_digest(digest) {
+                return Err(ImageParseError::InvalidDigest(digest.to_string()));
+            }
+        }
+        let name_start = name.rfind('/').map_or(0, |i| i + 1);
+        let (name, tag) = match name[name_start..].rfind(':') {
+            Some(i) => (&name[..name_start + i], Some(&name[name_start + i + 1..])),
+            None => (name, None),
+        };
+        if let Some(tag) = tag {
+            if !is_valid_tag(tag) {
+                return Err(ImageParseError::InvalidTag(tag.to_string()));
+            }
+        }
+        let mut components: Vec<&str> = name.split('/').collect();
+        if components.len() > 1 && is_registry(components[0]) {
+            let registry = components.remove(0);
+            if !is_valid_registry(registry) {
+                return Err(ImageParseError::InvalidRegistry(registry.to_string()));
+            }
+        }
+        if let Some(component) = components.iter().find(|c| !is_valid_path_component(c)) {
+            return Err(ImageParseError::InvalidRepository(component.to_string()));
+        }
+        Ok(ImageRef::parse(s))
+    }
+
+    /// Parses an `ImageRef` from a string, enforcing the whole OCI
+    /// distribution reference grammar: on top of [`ImageRef::try_parse`],
+    /// the length of the name and the algorithm and encoding of the digest
+    pub fn try_parse_strict(s: &str) -> Result<ImageRef, ImageParseError> {
+        let image_ref = ImageRef::try_parse(s)?;
+        let (name, digest) = match s.split_once('@') {
+            Some((name, digest)) => (name, Some(digest)),
+            None => (s, None),
+        };
+        let name = match name.rsplit_once(':') {
+            Some((repository, tag)) if !tag.contains('/') => repository,
+            _ => name,
+        };
+        if name.len() > MAX_NAME_LENGTH {
+            return Err(ImageParseError::NameTooLong(name.to_string()));
+        }
+        if let Some(digest) = digest.filter(|digest| !is_canonical_digest(digest)) {
+            return Err(ImageParseError::InvalidDigest(digest.to_string()));
+        }
+        Ok(image_ref)
+    }
+
     /// Parses an `ImageRef` from a string.
     ///
     /// This is not fallible, however malformed image strings may return
-    /// unexpected results.
+    /// unexpected results, see [`ImageRef::try_parse`].
     pub fn parse(s: &str) -> ImageRef {
         let parts: Vec<&str> = s.splitn(2, '/').collect();
         let (registry, mut image_full) = if parts.len() == 2 && is_registry(parts[0]) {
             // some 3rd party registry
-            (Some(parts[0].to_string()), parts[1].to_string())
+            (parts[0], parts[1].to_string())
         } else {
             // default to docker.io
-            (Some("docker.io".to_string()), s.to_string())
+            ("docker.io", s.to_string())
         };
+        let (host, port) = split_registry(registry);
 
-        if !image_full.chars().any(|c| c == '/') && registry.as_ref().unwrap().eq("docker.io") {
+        if !image_full.chars().any(|c| c == '/') && registry == "docker.io" {
             image_full = format!("library/{}", image_full);
         }
 
-        if let Some(at_pos) = image_full.find('@') {
-            // parts length is guaranteed to be at least 1 given an empty string
-            let (image, hash) = image_full.split_at(at_pos);
-
-            ImageRef {
-                registry,
-                image: image.to_string(),
-                hash: Some(hash[1..].to_string()),
-                tag: None,
-            }
-        } else {
-            // parts length is guaranteed to be at least 1 given an empty string
-            let parts: Vec<&str> = image_full.splitn(2, ':').collect();
-            let image = parts[0].to_string();
-            let tag = Some(
-                parts
-                    .get(1)
-                    .map_or_else(|| "latest".to_string(), |p| String::from(*p)),
-            );
+        let hash = image_full.find('@').map(|at_pos| {
+            let hash = image_full[at_pos + 1..].to_string();
+            image_full.truncate(at_pos);
+            hash
+        });
+        // parts length is guaranteed to be at least 1 given an empty string
+        let parts: Vec<&str> = image_full.splitn(2, ':').collect();
+        let image = parts[0].to_string();
+        let tag = match (parts.get(1), &hash) {
+            (Some(tag), _) => Some(tag.to_string()),
+            (None, Some(_)) => None,
+            (None, None) => Some("latest".to_string()),
+        };
 
-            ImageRef {
-                registry,
-                image,
-                tag,
-                hash: None,
-            }
+        ImageRef {
+            host: Some(host),
+            port,
+            image,
+            tag,
+            hash,
         }
     }
 }
 
+impl FromStr for ImageRef {
+    type Err = ImageParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ImageRef::try_parse(s)
+    }
+}
+
 impl fmt::Display for ImageRef {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if let Some(registry) = &self.registry {
+        if let Some(registry) = self.registry() {
             write!(f, "{}/", registry)?;
         }
 
@@ -82,7 +494,8 @@ impl fmt::Display for ImageRef {
 
         if let Some(tag) = &self.tag {
             write!(f, ":{}", tag)?;
-        } else if let Some(hash) = &self.hash {
+        }
+        if let Some(hash) = &self.hash {
             write!(f, "@{}", hash)?;
         }
 
@@ -99,7 +512,8 @@ mod tests {
         assert_eq!(
             ImageRef::parse("alpine:3.10"),
             ImageRef {
-                registry: Some("docker.io".to_string()),
+                host: Some("docker.io".into()),
+                port: None,
                 image: "library/alpine".into(),
                 tag: Some("3.10".into()),
                 hash: None
@@ -109,7 +523,8 @@ mod tests {
         assert_eq!(
             ImageRef::parse("library/nginx"),
             ImageRef {
-                registry: Some("docker.io".into()),
+                host: Some("docker.io".into()),
+                port: None,
                 image: "library/nginx".into(),
                 tag: Some("latest".into()),
                 hash: None
@@ -119,7 +534,8 @@ mod tests {
         assert_eq!(
             ImageRef::parse("fake_project/fake_image@fake_hash"),
             ImageRef {
-                registry: Some("docker.io".into()),
+                host: Some("docker.io".into()),
+                port: None,
                 image: "fake_project/fake_image".into(),
                 tag: None,
                 hash: Some("fake_hash".into())
@@ -130,7 +546,8 @@ mod tests {
         assert_eq!(
             ImageRef::parse("fake_project/fake_image@"),
             ImageRef {
-                registry: Some("docker.io".into()),
+                host: Some("docker.io".into()),
+                port: None,
                 image: "fake_project/fake_image".into(),
                 tag: None,
                 hash: Some("".into())
@@ -140,7 +557,8 @@ mod tests {
         assert_eq!(
             ImageRef::parse("fake_project/fake_image@sha256:"),
             ImageRef {
-                registry: Some("docker.io".into()),
+                host: Some("docker.io".into()),
+                port: None,
                 image: "fake_project/fake_image".into(),
                 tag: None,
                 hash: Some("sha256:".into())
@@ -153,7 +571,8 @@ mod tests {
         assert_eq!(
             ImageRef::parse("quay.io/prometheus/node-exporter:v0.18.1"),
             ImageRef {
-                registry: Some("quay.io".into()),
+                host: Some("quay.io".into()),
+                port: None,
                 image: "prometheus/node-exporter".into(),
                 tag: Some("v0.18.1".into()),
                 hash: None
@@ -163,7 +582,8 @@ mod tests {
         assert_eq!(
             ImageRef::parse("gcr.io/fake_project/fake_image:fake_tag"),
             ImageRef {
-                registry: Some("gcr.io".into()),
+                host: Some("gcr.io".into()),
+                port: None,
                 image: "fake_project/fake_image".into(),
                 tag: Some("fake_tag".into()),
                 hash: None
@@ -173,7 +593,8 @@ mod tests {
         assert_eq!(
             ImageRef::parse("gcr.io/fake_project/fake_image"),
             ImageRef {
-                registry: Some("gcr.io".into()),
+                host: Some("gcr.io".into()),
+                port: None,
                 image: "fake_project/fake_image".into(),
                 tag: Some("latest".into()),
                 hash: None
@@ -183,7 +604,8 @@ mod tests {
         assert_eq!(
             ImageRef::parse("gcr.io/fake_image"),
             ImageRef {
-                registry: Some("gcr.io".into()),
+                host: Some("gcr.io".into()),
+                port: None,
                 image: "fake_image".into(),
                 tag: Some("latest".into()),
                 hash: None
@@ -193,7 +615,8 @@ mod tests {
         assert_eq!(
             ImageRef::parse("quay.io/fake_project/fake_image@fake_hash"),
             ImageRef {
-                registry: Some("quay.io".into()),
+                host: Some("quay.io".into()),
+                port: None,
                 image: "fake_project/fake_image".into(),
                 tag: None,
                 hash: Some("fake_hash".into())
@@ -206,7 +629,8 @@ mod tests {
         assert_eq!(
             ImageRef::parse("localhost/foo"),
             ImageRef {
-                registry: Some("localhost".into()),
+                host: Some("localhost".into()),
+                port: None,
                 image: "foo".into(),
                 tag: Some("latest".into()),
                 hash: None
@@ -216,7 +640,8 @@ mod tests {
         assert_eq!(
             ImageRef::parse("localhost/foo:bar"),
             ImageRef {
-                registry: Some("localhost".into()),
+                host: Some("localhost".into()),
+                port: None,
                 image: "foo".into(),
                 tag: Some("bar".into()),
                 hash: None
@@ -226,7 +651,8 @@ mod tests {
         assert_eq!(
             ImageRef::parse("localhost/foo/bar"),
             ImageRef {
-                registry: Some("localhost".into()),
+                host: Some("localhost".into()),
+                port: None,
                 image: "foo/bar".into(),
                 tag: Some("latest".into()),
                 hash: None
@@ -236,7 +662,8 @@ mod tests {
         assert_eq!(
             ImageRef::parse("localhost/foo/bar:baz"),
             ImageRef {
-                registry: Some("localhost".into()),
+                host: Some("localhost".into()),
+                port: None,
                 image: "foo/bar".into(),
                 tag: Some("baz".into()),
                 hash: None
@@ -249,7 +676,8 @@ mod tests {
         assert_eq!(
             ImageRef::parse("example.com:1234/foo"),
             ImageRef {
-                registry: Some("example.com:1234".into()),
+                host: Some("example.com".into()),
+                port: Some(1234),
                 image: "foo".into(),
                 tag: Some("latest".into()),
                 hash: None
@@ -259,7 +687,8 @@ mod tests {
         assert_eq!(
             ImageRef::parse("example.com:1234/foo:bar"),
             ImageRef {
-                registry: Some("example.com:1234".into()),
+                host: Some("example.com".into()),
+                port: Some(1234),
                 image: "foo".into(),
                 tag: Some("bar".into()),
                 hash: None
@@ -269,7 +698,8 @@ mod tests {
         assert_eq!(
             ImageRef::parse("example.com:1234/foo/bar"),
             ImageRef {
-                registry: Some("example.com:1234".into()),
+                host: Some("example.com".into()),
+                port: Some(1234),
                 image: "foo/bar".into(),
                 tag: Some("latest".into()),
                 hash: None
@@ -279,7 +709,8 @@ mod tests {
         assert_eq!(
             ImageRef::parse("example.com:1234/foo/bar:baz"),
             ImageRef {
-                registry: Some("example.com:1234".into()),
+                host: Some("example.com".into()),
+                port: Some(1234),
                 image: "foo/bar".into(),
                 tag: Some("baz".into()),
                 hash: None
@@ -291,11 +722,346 @@ mod tests {
         assert_eq!(
             ImageRef::parse("example.com:1234/foo/bar/baz:qux"),
             ImageRef {
-                registry: Some("example.com:1234".into()),
+                host: Some("example.com".into()),
+                port: Some(1234),
                 image: "foo/bar/baz".into(),
                 tag: Some("qux".into()),
                 hash: None
             }
         );
     }
+
+    #[test]
+    fn test_has_template_placeholder() {
+        assert!(has_template_placeholder("{{ .Values.image }}"));
+        assert!(has_template_placeholder(
+            "quay.io/foo/bar:{{ .Chart.AppVersion }}"
+        ));
+        assert!(has_template_placeholder("${REGISTRY}/foo/bar"));
+        assert!(!has_template_placeholder("quay.io/foo/bar:v1.0.0"));
+        assert!(!has_template_placeholder("quay.io/foo/bar:{{"));
+        assert!(!has_template_placeholder("quay.io/foo/bar:$1"));
+    }
+
+    #[test]
+    fn test_unify_k8s_registry() {
+        assert_eq!(unify_k8s_registry("k8s.gcr.io"), "registry.k8s.io");
+        assert_eq!(
+            unify_k8s_registry("asia.k8s.gcr.io/pause:3.9"),
+            "registry.k8s.io/pause:3.9"
+        );
+        assert_eq!(
+            unify_k8s_registry("registry.k8s.io/pause:3.9"),
+            "registry.k8s.io/pause:3.9"
+        );
+        assert_eq!(
+            unify_k8s_registry("gcr.io/k8s.gcr.io:1.0"),
+            "gcr.io/k8s.gcr.io:1.0"
+        );
+    }
+
+    #[test]
+    fn test_image_path_depth() {
+        assert_eq!(ImageRef::parse("alpine").path_depth(), 2);
+        assert_eq!(ImageRef::parse("gcr.io/etcd").path_depth(), 1);
+        assert_eq!(
+            ImageRef::parse("example.com:1234/foo/bar/baz:qux").path_depth(),
+            3
+        );
+        assert!(ImageRef::parse("alpine").is_official());
+        assert!(ImageRef::parse("docker.io/library/alpine").is_official());
+        assert!(!ImageRef::parse("bitnami/nginx").is_official());
+        assert!(!ImageRef::parse("quay.io/library/alpine").is_official());
+    }
+
+    #[test]
+    fn test_image_registry_host_port() {
+        let image = ImageRef::parse("example.com:1234/foo/bar:baz");
+        assert_eq!(image.host(), Some("example.com"));
+        assert_eq!(image.port(), Some(1234));
+        assert_eq!(image.registry().as_deref(), Some("example.com:1234"));
+        assert_eq!(image.to_string(), "example.com:1234/foo/bar:baz");
+
+        let image = ImageRef::parse("alpine");
+        assert_eq!(image.host(), Some("docker.io"));
+        assert_eq!(image.port(), None);
+        assert_eq!(image.registry().as_deref(), Some("docker.io"));
+        assert_eq!(image.to_string(), "docker.io/library/alpine:latest");
+    }
+
+    #[test]
+    fn test_image_org() {
+        let image = ImageRef::parse("quay.io/bitnami/charts/redis");
+        assert_eq!(image.org(), Some("bitnami"));
+        assert_eq!(image.path_in_org(), "charts/redis");
+
+        let image = ImageRef::parse("gcr.io/etcd");
+        assert_eq!(image.org(), None);
+        assert_eq!(image.path_in_org(), "etcd");
+
+        assert_eq!(ImageRef::parse("alpine").org(), Some("library"));
+    }
+
+    #[test]
+    fn test_image_repository() {
+        assert_eq!(
+            ImageRef::parse("alpine:3.17").repository(),
+            "docker.io/library/alpine"
+        );
+        assert_eq!(
+            ImageRef::parse("localhost:5000/foo/bar@sha256:abc").repository(),
+            "localhost:5000/foo/bar"
+        );
+    }
+
+    #[test]
+    fn test_has_explicit_tag() {
+        assert!(has_explicit_tag("alpine:3.17"));
+        assert!(has_explicit_tag("alpine@sha256:abc"));
+        assert!(has_explicit_tag("example.com:1234/foo/bar:baz"));
+        assert!(!has_explicit_tag("alpine"));
+        assert!(!has_explicit_tag("example.com:1234/foo/bar"));
+        assert!(!has_explicit_tag("localhost:5000/foo"));
+    }
+
+    #[test]
+    fn test_image_match_source() {
+        let image = ImageRef::parse("us.gcr.io/google/etcd:3.5");
+        assert_eq!(image.match_source("*.gcr.io"), Some(1));
+        assert_eq!(image.match_source("*.gcr.io/google"), Some(2));
+        assert_eq!(image.match_source("*.gcr.io/*"), Some(1));
+        assert_eq!(image.match_source("*.gcr.io/goo*/etcd"), Some(3));
+        assert_eq!(image.match_source("*.gcr.io/google/etcd/*"), None);
+        assert_eq!(
+            ImageRef::parse("gcr.io/etcd").match_source("*.gcr.io"),
+            None
+        );
+
+        let image = ImageRef::parse("bitnami/nginx:1.25");
+        assert_eq!(image.match_source("docker.io/bitnami/*"), Some(2));
+        // `*` never crosses a component boundary
+        assert_eq!(image.match_source("docker.io/*nginx"), None);
+        assert_eq!(image.match_source("docker*/bitnami"), Some(2));
+    }
+
+    #[test]
+    fn test_image_match_source_boundaries() {
+        let image = ImageRef::parse("docker.io/foobar/app:v1");
+        assert_eq!(image.match_source("docker.io/foo"), None);
+        assert_eq!(image.match_source("docker.io/foobar"), Some(2));
+        assert_eq!(image.match_source("docker.io/foobar/"), Some(2));
+        assert_eq!(image.match_source("docker.io/foobar/app"), Some(3));
+        assert_eq!(image.match_source("docker.i"), None);
+        assert_eq!(
+            ImageRef::parse("localhost:5000/app").match_source("localhost:5000"),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_image_rebase() {
+        let image = ImageRef::parse("us.gcr.io/google/etcd:3.5");
+        assert_eq!(
+            image.rebase(1, "mirror.corp/gcr"),
+            "mirror.corp/gcr/google/etcd:3.5"
+        );
+        assert_eq!(image.rebase(3, "mirror.corp/etcd"), "mirror.corp/etcd:3.5");
+        assert_eq!(
+            ImageRef::parse("alpine@sha256:1234").rebase(2, "mirror.corp"),
+            "mirror.corp/alpine@sha256:1234"
+        );
+    }
+
+    #[test]
+    fn test_has_registry() {
+        assert!(!has_registry("alpine:3.19"));
+        assert!(!has_registry("bitnami/nginx"));
+        assert!(has_registry("docker.io/library/alpine"));
+        assert!(has_registry("localhost:5000/app"));
+        assert!(has_registry("localhost/app"));
+    }
+
+    #[test]
+    fn test_image_try_parse() {
+        for image in [
+            "alpine",
+            "alpine:3.19",
+            "docker.io/library/alpine:3.19",
+            "localhost:5000/my_app/app__v2:dev",
+            "Registry.Example.com/foo/bar-baz.qux",
+            "quay.io/foo/bar@sha256:1234",
+        ] {
+            assert_eq!(
+                image.parse::<ImageRef>(),
+                Ok(ImageRef::parse(image)),
+                "{}",
+                image
+            );
+        }
+
+        assert_eq!(ImageRef::try_parse(""), Err(ImageParseError::Empty));
+        assert_eq!(
+            ImageRef::try_parse("foo@@bar"),
+            Err(ImageParseError::InvalidDigest("@bar".into()))
+        );
+        assert_eq!(
+            ImageRef::try_parse("regi_stry.io/foo"),
+            Err(ImageParseError::InvalidRegistry("regi_stry.io".into()))
+        );
+        assert_eq!(
+            ImageRef::try_parse("localhost:50a0/foo"),
+            Err(ImageParseError::InvalidRegistry("localhost:50a0".into()))
+        );
+        assert_eq!(
+            ImageRef::try_parse("quay.io/Foo/bar"),
+            Err(ImageParseError::InvalidRepository("Foo".into()))
+        );
+        assert_eq!(
+            ImageRef::try_parse("quay.io/foo//bar"),
+            Err(ImageParseError::InvalidRepository("".into()))
+        );
+        assert_eq!(
+            ImageRef::try_parse("quay.io/foo/bar-"),
+            Err(ImageParseError::InvalidRepository("bar-".into()))
+        );
+        assert_eq!(
+            ImageRef::try_parse("alpine:.3"),
+            Err(ImageParseError::InvalidTag(".3".into()))
+        );
+        assert_eq!(
+            ImageRef::try_parse("alpine:"),
+            Err(ImageParseError::InvalidTag("".into()))
+        );
+    }
+
+    #[test]
+    fn test_image_try_parse_strict() {
+        let sha256 = format!("sha256:{}", "0123456789abcdef".repeat(4));
+        for image in [
+            "alpine:3.19".to_string(),
+            "localhost:5000/my_app/app__v2:dev".to_string(),
+            format!("quay.io/foo/bar:v1@{}", sha256),
+            format!("quay.io/foo/bar@sha512:{}", "ab".repeat(64)),
+            "quay.io/foo/bar@multihash+base58:QmRZxt2b1FVZPNqd8hsiykDL3TdBDeTSPX9Kv46HmX4Gx8"
+                .to_string(),
+        ] {
+            assert_eq!(
+                ImageRef::try_parse_strict(&image),
+                Ok(ImageRef::parse(&image)),
+                "{}",
+                image
+            );
+        }
+
+        // valid in the lenient grammar only
+        assert_eq!(
+            ImageRef::try_parse_strict("quay.io/foo/bar@sha256:1234"),
+            Err(ImageParseError::InvalidDigest("sha256:1234".into()))
+        );
+        let uppercase = sha256.to_uppercase().replacen("SHA256", "sha256", 1);
+        assert_eq!(
+            ImageRef::try_parse_strict(&format!("alpine@{}", uppercase)),
+            Err(ImageParseError::InvalidDigest(uppercase))
+        );
+        assert_eq!(
+            ImageRef::try_parse_strict("alpine@SHA256:abcd"),
+            Err(ImageParseError::InvalidDigest("SHA256:abcd".into()))
+        );
+        assert_eq!(
+            ImageRef::try_parse_strict("alpine@sha..256:abcd"),
+            Err(ImageParseError::InvalidDigest("sha..256:abcd".into()))
+        );
+        let name = format!("quay.io/{}", "a".repeat(250));
+        assert_eq!(
+            ImageRef::try_parse_strict(&format!("{}:v1", name)),
+            Err(ImageParseError::NameTooLong(name))
+        );
+        // still checked by the lenient grammar
+        assert_eq!(
+            ImageRef::try_parse_strict("quay.io/Foo/bar"),
+            Err(ImageParseError::InvalidRepository("Foo".into()))
+        );
+    }
+
+    #[test]
+    fn test_image_tag_and_digest() {
+        let image = ImageRef::parse("nginx:1.25@sha256:abc");
+        assert_eq!(
+            image,
+            ImageRef {
+                host: Some("docker.io".into()),
+                port: None,
+                image: "library/nginx".into(),
+                tag: Some("1.25".into()),
+                hash: Some("sha256:abc".into())
+            }
+        );
+        assert_eq!(image.to_string(), "docker.io/library/nginx:1.25@sha256:abc");
+        assert_eq!(
+            image.rebase(1, "mirror.corp"),
+            "mirror.corp/library/nginx:1.25@sha256:abc"
+        );
+        assert_eq!(
+            ImageRef::parse("localhost:5000/app:dev@sha256:abc").to_string(),
+            "localhost:5000/app:dev@sha256:abc"
+        );
+        assert!(ImageRef::try_parse("nginx:1.25@sha256:abc").is_ok());
+    }
+
+    #[test]
+    fn test_image_parse_ipv6_registry() {
+        let image = ImageRef::parse("[::1]:5000/myimage:dev");
+        assert_eq!(
+            image,
+            ImageRef {
+                host: Some("[::1]".into()),
+                port: Some(5000),
+                image: "myimage".into(),
+                tag: Some("dev".into()),
+                hash: None
+            }
+        );
+        assert_eq!(image.registry().as_deref(), Some("[::1]:5000"));
+        assert_eq!(image.to_string(), "[::1]:5000/myimage:dev");
+        assert_eq!(image.match_source("[::1]:5000"), Some(1));
+
+        let image = ImageRef::parse("[fd00::10]/team/app@sha256:abc");
+        assert_eq!(image.host(), Some("[fd00::10]"));
+        assert_eq!(image.port(), None);
+        assert_eq!(image.to_string(), "[fd00::10]/team/app@sha256:abc");
+
+        assert!(ImageRef::try_parse("[::1]:5000/myimage:dev").is_ok());
+        assert!(ImageRef::try_parse("[fd00::10]/team/app").is_ok());
+        assert_eq!(
+            ImageRef::try_parse("[::g]:5000/myimage"),
+            Err(ImageParseError::InvalidRegistry("[::g]:5000".into()))
+        );
+        assert_eq!(
+            ImageRef::try_parse("[::1/myimage"),
+            Err(ImageParseError::InvalidRegistry("[::1".into()))
+        );
+        assert!(has_registry("[::1]:5000/myimage"));
+    }
+
+    #[test]
+    fn test_unalias_registry() {
+        let aliases = HashMap::from([
+            ("index.docker.io".to_string(), "docker.io".to_string()),
+            ("mirror.gcr.io".to_string(), "docker.io".to_string()),
+        ]);
+        assert_eq!(
+            unalias_registry("index.docker.io/library/nginx", &aliases),
+            "docker.io/library/nginx"
+        );
+        assert_eq!(
+            ImageRef::parse(&unalias_registry("mirror.gcr.io/nginx:1.25", &aliases)).to_string(),
+            "docker.io/library/nginx:1.25"
+        );
+        assert_eq!(unalias_registry("index.docker.io", &aliases), "docker.io");
+        assert_eq!(unalias_registry("nginx", &aliases), "nginx");
+        assert_eq!(
+            unalias_registry("quay.io/index.docker.io", &aliases),
+            "quay.io/index.docker.io"
+        );
+    }
 }
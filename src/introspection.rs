@@ -0,0 +1,91 @@
+//! The `introspect` waPC function, letting fleet tooling verify that every
+//! policy server runs the intended build with the intended settings.
+
+use guest::prelude::*;
+use kubewarden_policy_sdk::wapc_guest as guest;
+
+use crate::settings::Settings;
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// Response of the `introspect` function
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Introspection {
+    /// Version of the policy
+    version: &'static str,
+    /// Settings enabled, that is set to a value different from the default
+    features: Vec<String>,
+    /// SHA-256 digest of the normalized settings
+    settings_digest: String,
+}
+
+impl Introspection {
+    fn new(settings: &Settings) -> serde_json::Result<Self> {
+        // maps are serialized with sorted keys, regardless of the order of
+        // the `HashMap` fields of the settings
+        let normalized = serde_json::to_value(settings)?;
+        let defaults = serde_json::to_value(Settings::default())?;
+        let features = normalized
+            .as_object()
+            .map(|fields| {
+                fields
+                    .iter()
+                    .filter(|(name, value)| defaults.get(name.as_str()) != Some(value))
+                    .map(|(name, _)| name.clone())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let digest = Sha256::digest(serde_json::to_string(&normalized)?.as_bytes());
+
+        Ok(Introspection {
+            version: env!("CARGO_PKG_VERSION"),
+            features,
+            settings_digest: format!("sha256:{:x}", digest),
+        })
+    }
+}
+
+/// waPC guest function to register under the name `introspect`, taking the
+/// settings of the policy as payload
+pub(crate) fn introspect(payload: &[u8]) -> CallResult {
+    let settings: Settings = if payload.is_empty() {
+        Settings::default()
+    } else {
+        serde_json::from_slice(payload)?
+    };
+    Ok(serde_json::to_vec(&Introspection::new(&settings)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn introspect_settings() {
+        let response: serde_json::Value = serde_json::from_slice(
+            &introspect(br#"{"repos": {"quay.io": "quay.example.com", "docker.io": "dockerhub.example.com"}, "unifyK8sRegistries": true}"#)
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(response["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(
+            response["features"],
+            serde_json::json!(["repos", "unifyK8sRegistries"])
+        );
+
+        // the digest does not depend on the order of the mappings
+        let reordered: serde_json::Value = serde_json::from_slice(
+            &introspect(br#"{"unifyK8sRegistries": true, "repos": {"docker.io": "dockerhub.example.com", "quay.io": "quay.example.com"}}"#)
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(response["settingsDigest"], reordered["settingsDigest"]);
+
+        let defaults: serde_json::Value =
+            serde_json::from_slice(&introspect(b"").unwrap()).unwrap();
+        assert_eq!(defaults["features"], serde_json::json!([]));
+        assert_ne!(response["settingsDigest"], defaults["settingsDigest"]);
+    }
+}
@@ -0,0 +1,157 @@
+//! Subset of JSONPath locating the images embedded in custom resources, e.g.
+//! `.spec.image` or `.spec.sidecars[*].image`.
+
+use serde_json::Value;
+
+/// Step of a JSON path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Segment {
+    /// Field of an object, `.name`
+    Field(String),
+    /// Item of an array, `[0]`
+    Index(usize),
+    /// Every item of an array, `[*]`
+    Wildcard,
+}
+
+/// Parses a JSON path made of fields and array indexes, optionally starting
+/// with `$`
+pub(crate) fn parse(path: &str) -> Result<Vec<Segment>, String> {
+    let mut rest = path.strip_prefix('$').unwrap_or(path);
+    let mut segments = Vec::new();
+    while !rest.is_empty() {
+        if let Some(tail) = rest.strip_prefix('.') {
+            let end = tail.find(['.', '[']).unwrap_or(tail.len());
+            if end == 0 {
+                return Err(format!("empty field in JSON path {}", path));
+            }
+            segments.push(Segment::Field(tail[..end].to_string()));
+            rest = &tail[end..];
+        } else if let Some(tail) = rest.strip_prefix('[') {
+            let Some(end) = tail.find(']') else {
+                return Err(format!("unclosed [ in JSON path {}", path));
+            };
+            segments.push(match &tail[..end] {
+                "*" => Segment::Wildcard,
+                index => Segment::Index(
+                    index
+                        .parse()
+                        .map_err(|_| format!("invalid index {} in JSON path {}", index, path))?,
+                ),
+            });
+            rest = &tail[end + 1..];
+        } else {
+            return Err(format!(
+                "JSON path {} must be made of .fields and [indexes]",
+                path
+            ));
+        }
+    }
+    if segments.is_empty() {
+        return Err(format!("empty JSON path {}", path));
+    }
+    Ok(segments)
+}
+
+/// Calls `f` with every string found at `segments` of `value`, along with
+/// its location, e.g. `.spec.sidecars[1].image`. Missing fields and values
+/// of another type are skipped.
+pub(crate) fn visit_strings<E>(
+    value: &mut Value,
+    segments: &[Segment],
+    location: &str,
+    f: &mut impl FnMut(&str, &mut String) -> Result<(), E>,
+) -> Result<(), E> {
+    let Some((segment, rest)) = segments.split_first() else {
+        if let Value::String(string) = value {
+            f(location, string)?;
+        }
+        return Ok(());
+    };
+    match (segment, value) {
+        (Segment::Field(name), Value::Object(fields)) => {
+            if let Some(field) = fields.get_mut(name) {
+                visit_strings(field, rest, &format!("{}.{}", location, name), f)?;
+            }
+        }
+        (Segment::Index(index), Value::Array(items)) => {
+            if let Some(item) = items.get_mut(*index) {
+                visit_strings(item, rest, &format!("{}[{}]", location, index), f)?;
+            }
+        }
+        (Segment::Wildcard, Value::Array(items)) => {
+            for (index, item) in items.iter_mut().enumerate() {
+                visit_strings(item, rest, &format!("{}[{}]", location, index), f)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn parse_paths() -> Result<(), ()> {
+        assert_eq!(
+            parse("$.spec.sidecars[*].image"),
+            Ok(vec![
+                Segment::Field("spec".to_string()),
+                Segment::Field("sidecars".to_string()),
+                Segment::Wildcard,
+                Segment::Field("image".to_string()),
+            ])
+        );
+        assert_eq!(
+            parse(".spec.images[1]"),
+            Ok(vec![
+                Segment::Field("spec".to_string()),
+                Segment::Field("images".to_string()),
+                Segment::Index(1),
+            ])
+        );
+        assert!(parse("spec.image").is_err());
+        assert!(parse(".spec..image").is_err());
+        assert!(parse(".spec.images[x]").is_err());
+        assert!(parse(".spec.images[0").is_err());
+        assert!(parse("$").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn visit_images() -> Result<(), ()> {
+        let mut value = json!({"spec": {
+            "image": "quay.io/prometheus/prometheus:v2.48.0",
+            "sidecars": [{"image": "alpine"}, {"name": "no-image"}, {"image": 42}]
+        }});
+        let mut visited = Vec::new();
+        for path in [".spec.image", ".spec.sidecars[*].image", ".spec.missing"] {
+            visit_strings::<()>(
+                &mut value,
+                &parse(path).unwrap(),
+                "",
+                &mut |location, image| {
+                    visited.push(format!("{}={}", location, image));
+                    image.insert_str(0, "mirror.corp/");
+                    Ok(())
+                },
+            )?;
+        }
+
+        assert_eq!(
+            visited,
+            vec![
+                ".spec.image=quay.io/prometheus/prometheus:v2.48.0",
+                ".spec.sidecars[0].image=alpine",
+            ]
+        );
+        assert_eq!(value["spec"]["sidecars"][0]["image"], "mirror.corp/alpine");
+
+        Ok(())
+    }
+}
@@ -1,19 +1,47 @@
 use guest::prelude::*;
+use k8s_openapi::api::apps::v1 as apiapps;
+use k8s_openapi::api::batch::v1 as apibatch;
 use k8s_openapi::api::core::v1 as apicore;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
 use kubewarden_policy_sdk::wapc_guest as guest;
 use lazy_static::lazy_static;
-use std::collections::hash_map::HashMap;
+use std::collections::BTreeMap;
 
 extern crate kubewarden_policy_sdk as kubewarden;
-use kubewarden::{logging, protocol_version_guest, request::ValidationRequest, validate_settings};
+use kubewarden::{
+    logging, protocol_version_guest,
+    request::{KubernetesAdmissionRequest, ValidationRequest},
+    response::ValidationResponse,
+    validate_settings,
+};
 
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+pub mod engine;
+mod host;
 mod image;
+mod introspection;
+mod jsonpath;
+mod matcher;
+mod mutation;
+mod patch;
+mod presets;
+mod report;
+mod schema;
 mod settings;
-use image::ImageRef;
-use settings::Settings;
+mod template;
+mod workload;
+use mutation::{
+    custom_resource_images, mutate_custom_resource, mutate_ephemeral_containers, mutate_workload,
+};
+use report::{Finding, Reason};
+use settings::{ConfigMapRef, EnforceMode, HostErrorPolicy, Settings, UpdatePolicy};
+use workload::Workload;
 
 use slog::{info, o, warn, Logger};
 
+/// Prefix of the annotations set by this policy
+const ANNOTATION_PREFIX: &str = "mutate-repos-policy.kubewarden.io";
+
 lazy_static! {
     static ref LOG_DRAIN: Logger = Logger::root(
         logging::KubewardenDrain::new(),
@@ -26,22 +54,137 @@ pub extern "C" fn wapc_init() {
     register_function("validate", validate);
     register_function("validate_settings", validate_settings::<Settings>);
     register_function("protocol_version", protocol_version_guest);
+    register_function("introspect", introspection::introspect);
+    register_function("settings_schema", schema::settings_schema);
 }
 
 fn validate(payload: &[u8]) -> CallResult {
     let validation_request: ValidationRequest<Settings> = ValidationRequest::new(payload)?;
 
-    info!(LOG_DRAIN, "starting validation");
+    let request = validation_request.request;
+    let mut settings = validation_request.settings;
+
+    // ephemeral containers are only ever added by updates of the subresource
+    if request.sub_resource != "ephemeralcontainers"
+        && !settings.mutates_operation(&request.operation)
+    {
+        info!(LOG_DRAIN, "operation is not mutated, skipping"; "operation" => &request.operation);
+        return kubewarden::accept_request();
+    }
+
+    if let Some(artifact) = &settings.mappings_artifact {
+        match load_artifact(&settings, artifact) {
+            Ok(merged) => settings = merged,
+            Err(e) if settings.on_host_error == HostErrorPolicy::FailClosed => {
+                return kubewarden::reject_request(Some(e), None, None, None);
+            }
+            Err(e) => {
+                warn!(LOG_DRAIN, "cannot load the mappings artifact, using the settings only"; "error" => e);
+            }
+        }
+    }
+
+    if let Some(config_map) = &settings.mappings_config_map {
+        match load_mappings(&settings, config_map) {
+            Ok(merged) => settings = merged,
+            Err(e) if settings.on_host_error == HostErrorPolicy::FailClosed => {
+                return kubewarden::reject_request(Some(e), None, None, None);
+            }
+            Err(e) => {
+                warn!(LOG_DRAIN, "cannot load the mappings ConfigMap, using the settings only"; "error" => e);
+            }
+        }
+    }
+
+    if let Some(merged) = settings.for_namespace(&request.namespace) {
+        settings = merged;
+    }
+
+    if !settings.has_rules()
+        && !settings.reject_templated_images
+        && !settings.reject_on_no_match
+        && !settings.reject_invalid_images
+    {
+        // nothing can be rewritten, do not bother parsing the object
+        return kubewarden::accept_request();
+    }
 
-    // TODO: you can unmarshal any Kubernetes API type you are interested in
-    match serde_json::from_value::<apicore::Pod>(validation_request.request.object) {
-        Ok(mut pod) => {
-            pod = mutate_pod(pod, &validation_request.settings);
-            let mutated_object = serde_json::to_value(pod)?;
-            kubewarden::mutate_request(mutated_object)
+    match request.kind.kind.as_str() {
+        "Pod" if request.sub_resource == "ephemeralcontainers" => {
+            let existing = ephemeral_container_names(&request.old_object);
+            evaluate(request, &settings, |pod, settings| {
+                mutate_ephemeral_containers(pod, &existing, settings)
+            })
+        }
+        "Pod" => evaluate(request, &settings, mutate_workload::<apicore::Pod>),
+        "ReplicationController" => evaluate(
+            request,
+            &settings,
+            mutate_workload::<apicore::ReplicationController>,
+        ),
+        "Deployment" => evaluate(request, &settings, mutate_workload::<apiapps::Deployment>),
+        "StatefulSet" => evaluate(request, &settings, mutate_workload::<apiapps::StatefulSet>),
+        "DaemonSet" => evaluate(request, &settings, mutate_workload::<apiapps::DaemonSet>),
+        "ReplicaSet" => evaluate(request, &settings, mutate_workload::<apiapps::ReplicaSet>),
+        "Job" => evaluate(request, &settings, mutate_workload::<apibatch::Job>),
+        "CronJob" => evaluate(request, &settings, mutate_workload::<apibatch::CronJob>),
+        kind => match settings
+            .custom_resources
+            .get(&custom_resource_key(&request))
+        {
+            Some(paths) => evaluate_custom_resource(request.clone(), &settings, paths),
+            None => {
+                warn!(LOG_DRAIN, "unsupported kind: this policy does not know how to evaluate this resource; accept it"; "kind" => kind);
+                kubewarden::accept_request()
+            }
+        },
+    }
+}
+
+/// Mutates the object of `request`, deserialized as the workload `W`, with
+/// `mutate`. Only the fields changed by `mutate` are written back into the
+/// raw object, every other field is returned as received.
+fn evaluate<W: Workload>(
+    request: KubernetesAdmissionRequest,
+    settings: &Settings,
+    mutate: impl FnOnce(W, &Settings) -> Result<(W, Vec<Finding>), Box<Finding>>,
+) -> CallResult {
+    match serde_json::from_value::<W>(request.object.clone()) {
+        Ok(workload) => {
+            if let Some(response) = exemption(
+                &request,
+                workload.metadata(),
+                workload.pod_metadata(),
+                settings,
+            ) {
+                return response;
+            }
+            if !mutates_update(&request, settings, workload_images::<W>) {
+                return kubewarden::accept_request();
+            }
+            let selected = match mirror_set(workload.metadata(), workload.pod_metadata(), settings)
+            {
+                Ok(selected) => selected,
+                Err(e) => return kubewarden::reject_request(Some(e), None, None, None),
+            };
+            let settings = selected.as_ref().unwrap_or(settings);
+            let before = serde_json::to_value(&workload)?;
+            let mut object = request.object.clone();
+            let result = match mutate(workload, settings) {
+                Ok((workload, findings)) => {
+                    patch::apply_changes(&mut object, &before, &serde_json::to_value(workload)?);
+                    Ok(findings)
+                }
+                Err(finding) => Err(finding),
+            };
+            let mut received = request.object.clone();
+            strip_server_owned_fields(&mut received);
+            if unchanged_update(&request, &received, &object, &result) {
+                return kubewarden::accept_request();
+            }
+            respond(&request, object, result, settings)
         }
         Err(_) => {
-            // TODO: handle as you wish
             // We were forwarded a request we cannot unmarshal or
             // understand, just accept it
             warn!(LOG_DRAIN, "cannot unmarshal resource: this policy does not know how to evaluate this resource; accept it");
@@ -50,37 +193,329 @@ fn validate(payload: &[u8]) -> CallResult {
     }
 }
 
-fn mutate_pod(mut pod: apicore::Pod, settings: &Settings) -> apicore::Pod {
-    let mut pod_spec = pod.spec.unwrap();
-    pod_spec.containers = mutate_containers(&pod_spec.containers, settings.repos.clone());
-    if let Some(init_containers) = &pod_spec.init_containers {
-        pod_spec.init_containers = Some(mutate_containers(init_containers, settings.repos.clone()));
-    }
-    pod.spec = Some(pod_spec);
-    pod
-}
-
-fn mutate_containers(
-    containers: &[apicore::Container],
-    repos: HashMap<String, String>,
-) -> Vec<apicore::Container> {
-    let ctrs = containers
-        .iter()
-        .map(|container| {
-            let mut ctr = container.clone();
-            if let Some(ctr_image) = &ctr.image {
-                let image = ImageRef::parse(ctr_image.as_str()).to_string();
-                for (src, dest) in repos.clone().into_iter() {
-                    if image.starts_with(&src) {
-                        ctr.image = Some(image.replace(&src, &dest));
-                        break;
-                    }
-                }
+/// Mutates the images found at the JSON `paths` of the custom resource of
+/// `request`
+fn evaluate_custom_resource(
+    request: KubernetesAdmissionRequest,
+    settings: &Settings,
+    paths: &[String],
+) -> CallResult {
+    let metadata: ObjectMeta =
+        serde_json::from_value(request.object["metadata"].clone()).unwrap_or_default();
+    if let Some(response) = exemption(&request, &metadata, None, settings) {
+        return response;
+    }
+    if !mutates_update(&request, settings, |object| {
+        Some(custom_resource_images(object, paths))
+    }) {
+        return kubewarden::accept_request();
+    }
+    let selected = match mirror_set(&metadata, None, settings) {
+        Ok(selected) => selected,
+        Err(e) => return kubewarden::reject_request(Some(e), None, None, None),
+    };
+    let settings = selected.as_ref().unwrap_or(settings);
+    let mut object = request.object.clone();
+    strip_server_owned_fields(&mut object);
+    let received = object.clone();
+    let result = mutate_custom_resource(&mut object, paths, settings);
+    if unchanged_update(&request, &received, &object, &result) {
+        return kubewarden::accept_request();
+    }
+    respond(&request, object, result, settings)
+}
+
+/// Removes the `status` and `managedFields` of a raw object, which are owned
+/// by the API server and never echoed back in the mutated object
+fn strip_server_owned_fields(object: &mut serde_json::Value) {
+    if let Some(fields) = object.as_object_mut() {
+        fields.remove("status");
+    }
+    if let Some(fields) = object["metadata"].as_object_mut() {
+        fields.remove("managedFields");
+    }
+}
+
+/// Returns the response admitting the object of `request` untouched, when
+/// it carries the break-glass annotation, or when the object or its pods
+/// opt out of mutation
+fn exemption(
+    request: &KubernetesAdmissionRequest,
+    metadata: &ObjectMeta,
+    pod_metadata: Option<&ObjectMeta>,
+    settings: &Settings,
+) -> Option<CallResult> {
+    if let Some(reason) = bypass_reason(metadata, settings) {
+        let key = settings.bypass_annotation.as_deref().unwrap_or_default();
+        warn!(LOG_DRAIN, "BREAK-GLASS: admitting workload without mutation";
+            "annotation" => key,
+            "reason" => reason,
+            "kind" => &request.kind.kind,
+            "name" => metadata.name.as_deref().unwrap_or(&request.name),
+            "namespace" => &request.namespace,
+            "user" => &request.user_info.username);
+        return Some(accept_request_with_warning(format!(
+            "image mirroring bypassed by the {} annotation: {}",
+            key, reason
+        )));
+    }
+    let skip = settings.skip_annotation();
+    if opted_out(metadata, &skip) || pod_metadata.is_some_and(|metadata| opted_out(metadata, &skip))
+    {
+        info!(LOG_DRAIN, "workload opted out of mutation, skipping";
+            "annotation" => skip,
+            "kind" => &request.kind.kind,
+            "name" => metadata.name.as_deref().unwrap_or(&request.name),
+            "namespace" => &request.namespace);
+        return Some(kubewarden::accept_request());
+    }
+    None
+}
+
+/// Returns the settings of the mirror set selected by the mirror annotation
+/// of the pods, or else of the object, if any. Unknown mirror sets are an
+/// error.
+fn mirror_set(
+    metadata: &ObjectMeta,
+    pod_metadata: Option<&ObjectMeta>,
+    settings: &Settings,
+) -> Result<Option<Settings>, String> {
+    let key = settings::mirror_annotation();
+    let name = pod_metadata
+        .into_iter()
+        .chain(Some(metadata))
+        .find_map(|metadata| metadata.annotations.as_ref()?.get(&key));
+    name.map(|name| settings.for_mirror_set(name)).transpose()
+}
+
+/// Determines if `request` is mutated, when it is an update: with the
+/// `imagesChanged` update policy, only if the `images` of the object differ
+/// from the ones of the old object
+fn mutates_update(
+    request: &KubernetesAdmissionRequest,
+    settings: &Settings,
+    images: impl Fn(&serde_json::Value) -> Option<Vec<String>>,
+) -> bool {
+    if request.operation != "UPDATE"
+        || settings.on_update != UpdatePolicy::ImagesChanged
+        || images(&request.object) != images(&request.old_object)
+    {
+        return true;
+    }
+    info!(LOG_DRAIN, "update does not change the images, skipping";
+        "kind" => &request.kind.kind,
+        "name" => &request.name,
+        "namespace" => &request.namespace);
+    false
+}
+
+/// Determines if `request` is an update left unchanged by the mutation of
+/// the `received` object into `object`, e.g. because its images were already
+/// rewritten when it was created. Such updates are accepted as is, rather
+/// than returning the same object, which would conflict with other
+/// controllers.
+fn unchanged_update(
+    request: &KubernetesAdmissionRequest,
+    received: &serde_json::Value,
+    object: &serde_json::Value,
+    result: &Result<Vec<Finding>, Box<Finding>>,
+) -> bool {
+    if request.operation != "UPDATE" || result.is_err() || received != object {
+        return false;
+    }
+    info!(LOG_DRAIN, "update needs no image rewrite, skipping";
+        "kind" => &request.kind.kind,
+        "name" => &request.name,
+        "namespace" => &request.namespace);
+    true
+}
+
+/// Returns the images of the pods run by the workload `object`
+fn workload_images<W: Workload>(object: &serde_json::Value) -> Option<Vec<String>> {
+    let mut workload = serde_json::from_value::<W>(object.clone()).ok()?;
+    let (_, pod_spec) = workload.pod_mut()?;
+    Some(
+        workload::images(pod_spec)
+            .into_iter()
+            .map(String::from)
+            .collect(),
+    )
+}
+
+/// Returns the response to the mutation of the object of `request` into
+/// `object`: the mutated object, or the rejection of the mutation when
+/// validating only
+fn respond(
+    request: &KubernetesAdmissionRequest,
+    object: serde_json::Value,
+    result: Result<Vec<Finding>, Box<Finding>>,
+    settings: &Settings,
+) -> CallResult {
+    match result {
+        Ok(findings) if settings.dry_run => {
+            log_rewrites(request, &findings, true);
+            if let Some(message) =
+                report::violations(&findings).filter(|_| settings.enforce == EnforceMode::Validate)
+            {
+                info!(LOG_DRAIN, "request would be rejected, dry run"; "message" => message);
             }
-            ctr
-        })
-        .collect();
-    ctrs
+            accept_dry_run(&findings)
+        }
+        Err(finding) if settings.dry_run => {
+            info!(LOG_DRAIN, "request would be rejected, dry run"; "message" => finding.to_string());
+            accept_dry_run(&[*finding])
+        }
+        Ok(findings) if settings.enforce == EnforceMode::Validate => {
+            match report::violations(&findings) {
+                Some(message) => kubewarden::reject_request(
+                    Some(message),
+                    None,
+                    report::audit_annotations(&findings),
+                    None,
+                ),
+                None => kubewarden::accept_request(),
+            }
+        }
+        Ok(findings) => {
+            log_rewrites(request, &findings, false);
+            Ok(serde_json::to_vec(&ValidationResponse {
+                accepted: true,
+                message: None,
+                code: None,
+                mutated_object: Some(object),
+                audit_annotations: report::audit_annotations(&findings),
+                warnings: if settings.emit_warnings {
+                    report::warnings(&findings)
+                } else {
+                    None
+                },
+            })?)
+        }
+        Err(finding) => kubewarden::reject_request(
+            Some(finding.to_string()),
+            None,
+            report::audit_annotations(&[*finding]),
+            None,
+        ),
+    }
+}
+
+/// Create an acceptance response, without mutation, carrying the audit
+/// annotations of the rewrites planned in dry run
+fn accept_dry_run(findings: &[Finding]) -> CallResult {
+    Ok(serde_json::to_vec(&ValidationResponse {
+        accepted: true,
+        message: None,
+        code: None,
+        mutated_object: None,
+        audit_annotations: report::audit_annotations(findings),
+        warnings: None,
+    })?)
+}
+
+/// Logs an audit record of every image rewritten in the object of
+/// `request`, identifying the resource, the container and the matching rule.
+/// In dry run the images are only planned to be rewritten.
+fn log_rewrites(request: &KubernetesAdmissionRequest, findings: &[Finding], dry_run: bool) {
+    let metadata = &request.object["metadata"];
+    // objects created with generateName have no name yet
+    let name = metadata["name"]
+        .as_str()
+        .or_else(|| metadata["generateName"].as_str())
+        .unwrap_or(&request.name);
+    for finding in findings.iter().filter(|f| f.reason == Reason::Rewritten) {
+        info!(LOG_DRAIN, "image rewritten";
+            "kind" => &request.kind.kind,
+            "namespace" => &request.namespace,
+            "name" => name,
+            "container" => &finding.container,
+            "original" => &finding.original,
+            "rewritten" => &finding.rewritten,
+            "rule" => &finding.rule,
+            "team" => &finding.attribution.team,
+            "cost_center" => &finding.attribution.cost_center,
+            "user" => &request.user_info.username,
+            "dry_run" => dry_run);
+    }
+}
+
+/// Returns the key of the kind of `request` in the `customResources`
+/// mapping, `group/version/Kind`
+fn custom_resource_key(request: &KubernetesAdmissionRequest) -> String {
+    let kind = &request.kind;
+    if kind.group.is_empty() {
+        format!("{}/{}", kind.version, kind.kind)
+    } else {
+        format!("{}/{}/{}", kind.group, kind.version, kind.kind)
+    }
+}
+
+/// Returns the settings merged with the mappings annotating the manifest of
+/// `artifact`, fetched through the OCI host capability
+fn load_artifact(settings: &Settings, artifact: &str) -> Result<Settings, String> {
+    let manifest = host::with_host(|host| host.oci_manifest(artifact))
+        .map_err(|e| format!("cannot load the mappings artifact {}: {}", artifact, e))?;
+    let annotations: BTreeMap<String, String> =
+        serde_json::from_value(manifest["annotations"].clone()).unwrap_or_default();
+    settings
+        .merge_artifact(&annotations)
+        .map_err(|e| format!("invalid mappings artifact {}: {}", artifact, e))
+}
+
+/// Returns the settings merged with the mappings of `config_map`, fetched
+/// through the context-aware Kubernetes host capability
+fn load_mappings(settings: &Settings, config_map: &ConfigMapRef) -> Result<Settings, String> {
+    let data = host::with_host(|host| host.config_map(&config_map.namespace, &config_map.name))
+        .map_err(|e| format!("cannot load the mappings ConfigMap {}: {}", config_map, e))?
+        .data
+        .unwrap_or_default();
+    settings
+        .merge_config_map(&data)
+        .map_err(|e| format!("invalid mappings ConfigMap {}: {}", config_map, e))
+}
+
+/// Returns the names of the ephemeral containers of the pod `object`
+fn ephemeral_container_names(object: &serde_json::Value) -> Vec<String> {
+    serde_json::from_value::<apicore::Pod>(object.clone())
+        .ok()
+        .and_then(|pod| pod.spec?.ephemeral_containers)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|container| container.name)
+        .collect()
+}
+
+/// Determines if the annotation, or label, `key` of `metadata` is `true`
+fn opted_out(metadata: &ObjectMeta, key: &str) -> bool {
+    [&metadata.annotations, &metadata.labels].iter().any(|map| {
+        map.as_ref()
+            .and_then(|map| map.get(key))
+            .is_some_and(|value| value == "true")
+    })
+}
+
+/// Returns the value of the break-glass annotation, if the workload carries it
+fn bypass_reason<'a>(metadata: &'a ObjectMeta, settings: &Settings) -> Option<&'a str> {
+    let key = settings.bypass_annotation.as_ref()?;
+    metadata
+        .annotations
+        .as_ref()?
+        .get(key)
+        .map(String::as_str)
+        .filter(|value| !value.is_empty())
+}
+
+/// Create an acceptance response, without mutation, carrying a warning for
+/// the requesting API client
+fn accept_request_with_warning(warning: String) -> CallResult {
+    Ok(serde_json::to_vec(&ValidationResponse {
+        accepted: true,
+        message: None,
+        code: None,
+        mutated_object: None,
+        audit_annotations: None,
+        warnings: Some(vec![warning]),
+    })?)
 }
 
 #[cfg(test)]
@@ -88,29 +523,50 @@ mod tests {
     use super::*;
 
     use kubewarden_policy_sdk::test::Testcase;
+    use settings::{Destination, Operation};
+    use std::collections::hash_map::HashMap;
+
+    /// Returns the test case evaluating the request of `fixture_file` with
+    /// `settings`
+    fn testcase(
+        name: &str,
+        fixture_file: &str,
+        expected_validation_result: bool,
+        settings: Settings,
+    ) -> Testcase<Settings> {
+        Testcase {
+            name: name.to_string(),
+            fixture_file: fixture_file.to_string(),
+            expected_validation_result,
+            settings,
+        }
+    }
+
+    /// Returns the repo mappings of `entries`, by source
+    fn repos(entries: &[(&str, &str)]) -> HashMap<String, Destination> {
+        entries
+            .iter()
+            .map(|(src, dest)| (src.to_string(), (*dest).into()))
+            .collect()
+    }
 
     #[test]
     fn mutate_pod_repos() -> Result<(), ()> {
         let request_file = "test_data/pod_creation.json";
-        let tc = Testcase {
-            name: String::from("Valid name"),
-            fixture_file: String::from(request_file),
-            expected_validation_result: true,
-            settings: Settings {
-                repos: HashMap::from([
-                    ("quay.io".to_string(), "quay.tencentcloudcr.com".to_string()),
-                    ("gcr.io".to_string(), "gcr.tencentcloudcr.com".to_string()),
-                    (
-                        "docker.io".to_string(),
-                        "dockerhub.tencentcloudcr.com".to_string(),
-                    ),
-                    (
-                        "k8s.gcr.io".to_string(),
-                        "k8s.tencentcloudcr.com".to_string(),
-                    ),
+        let tc = testcase(
+            "Valid name",
+            request_file,
+            true,
+            Settings {
+                repos: repos(&[
+                    ("quay.io", "quay.tencentcloudcr.com"),
+                    ("gcr.io", "gcr.tencentcloudcr.com"),
+                    ("docker.io", "dockerhub.tencentcloudcr.com"),
+                    ("k8s.gcr.io", "k8s.tencentcloudcr.com"),
                 ]),
+                ..Default::default()
             },
-        };
+        );
 
         let res = tc.eval(validate).unwrap();
         assert!(
@@ -120,6 +576,656 @@ mod tests {
         );
         info!(LOG_DRAIN, "{}", res.mutated_object.unwrap());
 
+        let results: serde_json::Value = serde_json::from_str(
+            &res.audit_annotations.unwrap()["mutate-repos-policy.kubewarden.io/results"],
+        )
+        .unwrap();
+        assert_eq!(
+            results[1],
+            serde_json::json!({
+                "rule": "repos/gcr.io",
+                "container": "etcd",
+                "original": "gcr.io/etcd:3.5.6-0",
+                "rewritten": "gcr.tencentcloudcr.com/etcd:3.5.6-0",
+                "reason": "rewritten"
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn templated_image_is_left_untouched() -> Result<(), ()> {
+        let tc = testcase(
+            "Templated image",
+            "test_data/pod_creation_templated.json",
+            true,
+            Settings {
+                repos: repos(&[("docker.io", "dockerhub.tencentcloudcr.com")]),
+                ..Default::default()
+            },
+        );
+
+        let res = tc.eval(validate).unwrap();
+        let mutated_object = res.mutated_object.unwrap();
+        assert_eq!(
+            mutated_object["spec"]["containers"][0]["image"],
+            "{{ .Values.image.repository }}:{{ .Values.image.tag }}"
+        );
+        assert_eq!(
+            mutated_object["spec"]["containers"][1]["image"],
+            "dockerhub.tencentcloudcr.com/library/busybox:latest"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn templated_image_is_rejected() -> Result<(), ()> {
+        let tc = testcase(
+            "Reject templated image",
+            "test_data/pod_creation_templated.json",
+            false,
+            Settings {
+                reject_templated_images: true,
+                ..Default::default()
+            },
+        );
+
+        let res = tc.eval(validate).unwrap();
+        assert!(res.mutated_object.is_none());
+        assert_eq!(
+            res.message.as_deref(),
+            Some(
+                "nginx: {{ .Values.image.repository }}:{{ .Values.image.tag }} \
+                 (unresolved template placeholder)"
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_image_is_rejected_without_rules() -> Result<(), ()> {
+        let tc = testcase(
+            "Reject invalid image",
+            "test_data/pod_creation_invalid.json",
+            false,
+            Settings {
+                reject_invalid_images: true,
+                ..Default::default()
+            },
+        );
+
+        let res = tc.eval(validate).unwrap();
+        assert!(res.mutated_object.is_none());
+        assert!(res
+            .message
+            .unwrap()
+            .starts_with("nginx: quay.io/Foo/nginx:1.25"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn request_replication_of_missing_images() -> Result<(), ()> {
+        host::mock(host::MockHost {
+            digests: HashMap::from([(
+                "dockerhub.tencentcloudcr.com/library/alpine:3.17".to_string(),
+                "sha256:1234".to_string(),
+            )]),
+            ..Default::default()
+        });
+        let tc = testcase(
+            "Request replication",
+            "test_data/pod_creation.json",
+            true,
+            Settings {
+                repos: repos(&[("docker.io", "dockerhub.tencentcloudcr.com")]),
+                request_replication: true,
+                ..Default::default()
+            },
+        );
+
+        let res = tc.eval(validate).unwrap();
+        let mutated_object = res.mutated_object.unwrap();
+        assert_eq!(
+            mutated_object["spec"]["containers"][0]["image"],
+            "bitnami/nginx"
+        );
+        assert_eq!(
+            mutated_object["spec"]["initContainers"][0]["image"],
+            "dockerhub.tencentcloudcr.com/library/alpine:3.17"
+        );
+        assert_eq!(
+            mutated_object["metadata"]["annotations"]
+                ["mutate-repos-policy.kubewarden.io/mirror-requested"],
+            "dockerhub.tencentcloudcr.com/bitnami/nginx:latest"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn bypass_annotation_skips_mutation() -> Result<(), ()> {
+        let mut tc = testcase(
+            "Break-glass annotation",
+            "test_data/pod_creation_bypass.json",
+            true,
+            Settings {
+                repos: repos(&[("docker.io", "dockerhub.tencentcloudcr.com")]),
+                bypass_annotation: Some("example.com/break-glass".to_string()),
+                ..Default::default()
+            },
+        );
+
+        let res = tc.eval(validate).unwrap();
+        assert!(res.mutated_object.is_none());
+        assert_eq!(
+            res.warnings,
+            Some(vec![String::from(
+                "image mirroring bypassed by the example.com/break-glass annotation: \
+                 INC-1234 mirror outage"
+            )])
+        );
+
+        // the annotation has no effect unless configured
+        tc.settings.bypass_annotation = None;
+        let res = tc.eval(validate).unwrap();
+        assert!(res.mutated_object.is_some());
+        assert!(res.warnings.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn mutate_deployment_pod_template() -> Result<(), ()> {
+        let tc = testcase(
+            "Deployment",
+            "test_data/deployment_creation.json",
+            true,
+            Settings {
+                repos: repos(&[("docker.io", "dockerhub.tencentcloudcr.com")]),
+                ..Default::default()
+            },
+        );
+
+        let res = tc.eval(validate).unwrap();
+        let mutated_object = res.mutated_object.unwrap();
+        let pod_spec = &mutated_object["spec"]["template"]["spec"];
+        assert_eq!(
+            pod_spec["containers"][0]["image"],
+            "dockerhub.tencentcloudcr.com/bitnami/nginx:latest"
+        );
+        assert_eq!(
+            pod_spec["initContainers"][0]["image"],
+            "dockerhub.tencentcloudcr.com/library/alpine:3.17"
+        );
+        assert_eq!(mutated_object["kind"], "Deployment");
+        assert!(res.warnings.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn mirror_set_annotation() -> Result<(), ()> {
+        let mut tc: Testcase<Settings> = testcase(
+            "Mirror set",
+            "test_data/deployment_creation_mirror.json",
+            true,
+            serde_json::from_value(serde_json::json!({
+                "repos": {"docker.io": "mirror.corp/docker"},
+                "mirrorSets": {"edge-mirror": {"repos": {"docker.io": "edge.mirror.corp/docker"}}}
+            }))
+            .unwrap(),
+        );
+
+        let res = tc.eval(validate).unwrap();
+        assert_eq!(
+            res.mutated_object.unwrap()["spec"]["template"]["spec"]["containers"][0]["image"],
+            "edge.mirror.corp/docker/bitnami/nginx:latest"
+        );
+
+        tc.settings.mirror_sets.clear();
+        tc.expected_validation_result = false;
+        let res = tc.eval(validate).unwrap();
+        assert!(res
+            .message
+            .unwrap()
+            .starts_with("unknown mirror set edge-mirror"));
+
+        // mirror sets alone are enough to evaluate the objects
+        tc.settings = serde_json::from_value(serde_json::json!({
+            "mirrorSets": {"edge-mirror": {"repos": {"docker.io": "edge.mirror.corp/docker"}}}
+        }))
+        .unwrap();
+        tc.expected_validation_result = true;
+        let res = tc.eval(validate).unwrap();
+        assert_eq!(
+            res.mutated_object.unwrap()["spec"]["template"]["spec"]["containers"][0]["image"],
+            "edge.mirror.corp/docker/bitnami/nginx:latest"
+        );
+
+        tc.settings = serde_json::from_value(serde_json::json!({
+            "mirrorSets": {"backup": {"repos": {"docker.io": "backup.corp/docker"}}}
+        }))
+        .unwrap();
+        tc.expected_validation_result = false;
+        let res = tc.eval(validate).unwrap();
+        assert!(res
+            .message
+            .unwrap()
+            .starts_with("unknown mirror set edge-mirror"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn update_operations() -> Result<(), ()> {
+        let mut tc = testcase(
+            "Deployment update",
+            "test_data/deployment_update.json",
+            true,
+            Settings {
+                repos: repos(&[("docker.io", "dockerhub.tencentcloudcr.com")]),
+                ..Default::default()
+            },
+        );
+
+        // only CREATE by default
+        let res = tc.eval(validate).unwrap();
+        assert!(res.mutated_object.is_none());
+
+        tc.settings.operations = Some(vec![Operation::Create, Operation::Update]);
+        let res = tc.eval(validate).unwrap();
+        let mutated_object = res.mutated_object.unwrap();
+        assert_eq!(
+            mutated_object["spec"]["template"]["spec"]["containers"][0]["image"],
+            "dockerhub.tencentcloudcr.com/bitnami/nginx:latest"
+        );
+
+        // the update only scales the deployment
+        tc.settings.on_update = UpdatePolicy::ImagesChanged;
+        let res = tc.eval(validate).unwrap();
+        assert!(res.mutated_object.is_none());
+
+        // no image needs to be rewritten
+        tc.settings.on_update = UpdatePolicy::Always;
+        tc.settings.repos = repos(&[("quay.io", "quay.example.com")]);
+        let res = tc.eval(validate).unwrap();
+        assert!(res.mutated_object.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn mutate_ephemeral_containers_subresource() -> Result<(), ()> {
+        let tc = testcase(
+            "Ephemeral containers",
+            "test_data/pod_ephemeralcontainers_update.json",
+            true,
+            Settings {
+                repos: repos(&[("docker.io", "dockerhub.tencentcloudcr.com")]),
+                ..Default::default()
+            },
+        );
+
+        let res = tc.eval(validate).unwrap();
+        let spec = &res.mutated_object.unwrap()["spec"];
+        assert_eq!(spec["containers"][0]["image"], "bitnami/nginx");
+        assert_eq!(spec["ephemeralContainers"][0]["image"], "busybox:1.28");
+        assert_eq!(
+            spec["ephemeralContainers"][1]["image"],
+            "dockerhub.tencentcloudcr.com/library/busybox:1.28"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn skip_annotation_opts_out() -> Result<(), ()> {
+        let mut tc = testcase(
+            "Skip annotation",
+            "test_data/deployment_creation_skip.json",
+            true,
+            Settings {
+                repos: repos(&[("docker.io", "dockerhub.tencentcloudcr.com")]),
+                ..Default::default()
+            },
+        );
+
+        let res = tc.eval(validate).unwrap();
+        assert!(res.mutated_object.is_none());
+
+        tc.settings.skip_annotation = Some("example.com/skip".to_string());
+        let res = tc.eval(validate).unwrap();
+        assert!(res.mutated_object.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn emit_warnings() -> Result<(), ()> {
+        let tc = testcase(
+            "Warnings",
+            "test_data/deployment_creation.json",
+            true,
+            Settings {
+                repos: repos(&[("docker.io", "dockerhub.tencentcloudcr.com")]),
+                emit_warnings: true,
+                ..Default::default()
+            },
+        );
+
+        let res = tc.eval(validate).unwrap();
+        assert_eq!(
+            res.warnings,
+            Some(vec![
+                String::from(
+                    "container \"nginx\": image rewritten from bitnami/nginx to \
+                     dockerhub.tencentcloudcr.com/bitnami/nginx:latest"
+                ),
+                String::from(
+                    "container \"alpine\": image rewritten from alpine:3.17 to \
+                     dockerhub.tencentcloudcr.com/library/alpine:3.17"
+                ),
+            ])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_only_enforcement() -> Result<(), ()> {
+        let mut tc = testcase(
+            "Validate mode",
+            "test_data/deployment_creation.json",
+            false,
+            Settings {
+                repos: repos(&[("docker.io", "dockerhub.tencentcloudcr.com")]),
+                enforce: EnforceMode::Validate,
+                ..Default::default()
+            },
+        );
+
+        let res = tc.eval(validate).unwrap();
+        assert!(res.mutated_object.is_none());
+        assert_eq!(
+            res.message.as_deref(),
+            Some(
+                "images not pulled from an approved mirror: \
+                 container \"nginx\" uses bitnami/nginx instead of \
+                 dockerhub.tencentcloudcr.com/bitnami/nginx:latest; \
+                 container \"alpine\" uses alpine:3.17 instead of \
+                 dockerhub.tencentcloudcr.com/library/alpine:3.17"
+            )
+        );
+
+        // images already pointing at the mirror are accepted
+        tc.settings.repos = repos(&[("quay.io", "quay.example.com")]);
+        tc.expected_validation_result = true;
+        let res = tc.eval(validate).unwrap();
+        assert!(res.mutated_object.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn dry_run() -> Result<(), ()> {
+        let mut tc = testcase(
+            "Dry run",
+            "test_data/deployment_creation.json",
+            true,
+            Settings {
+                repos: repos(&[("docker.io", "dockerhub.tencentcloudcr.com")]),
+                emit_warnings: true,
+                dry_run: true,
+                ..Default::default()
+            },
+        );
+
+        let res = tc.eval(validate).unwrap();
+        assert!(res.mutated_object.is_none());
+        assert!(res.warnings.is_none());
+        let results = &res.audit_annotations.unwrap()[&format!("{}/results", ANNOTATION_PREFIX)];
+        assert!(results.contains("dockerhub.tencentcloudcr.com/library/alpine:3.17"));
+
+        // requests that would be rejected are accepted too
+        tc.settings.enforce = EnforceMode::Validate;
+        let res = tc.eval(validate).unwrap();
+        assert!(res.mutated_object.is_none());
+
+        tc.settings.enforce = EnforceMode::Mutate;
+        tc.settings.reject_on_no_match = true;
+        tc.settings.repos = repos(&[("quay.io", "quay.example.com")]);
+        let res = tc.eval(validate).unwrap();
+        assert!(res.mutated_object.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn mappings_config_map() -> Result<(), ()> {
+        let config_map: apicore::ConfigMap = serde_json::from_value(serde_json::json!({
+            "metadata": {"name": "mirrors", "namespace": "kubewarden"},
+            "data": {
+                "repos": r#"{"docker.io": "mirror.corp/docker"}"#,
+                "rules": r#"[{"source": "gcr.io", "destination": "mirror.corp/gcr"}]"#
+            }
+        }))
+        .unwrap();
+        host::mock(host::MockHost {
+            config_maps: HashMap::from([("kubewarden/mirrors".to_string(), config_map)]),
+            ..Default::default()
+        });
+        let mut tc = testcase(
+            "Mappings ConfigMap",
+            "test_data/pod_creation.json",
+            true,
+            Settings {
+                repos: repos(&[("docker.io", "dockerhub.example.com")]),
+                mappings_config_map: Some(settings::ConfigMapRef {
+                    namespace: "kubewarden".to_string(),
+                    name: "mirrors".to_string(),
+                }),
+                ..Default::default()
+            },
+        );
+
+        let res = tc.eval(validate).unwrap();
+        let mutated_object = res.mutated_object.unwrap();
+        assert_eq!(
+            mutated_object["spec"]["containers"][0]["image"],
+            "mirror.corp/docker/bitnami/nginx:latest"
+        );
+        assert_eq!(
+            mutated_object["spec"]["containers"][1]["image"],
+            "mirror.corp/gcr/etcd:3.5.6-0"
+        );
+
+        // a missing ConfigMap falls back to the settings, unless failing closed
+        tc.settings.mappings_config_map.as_mut().unwrap().name = "missing".to_string();
+        let res = tc.eval(validate).unwrap();
+        assert_eq!(
+            res.mutated_object.unwrap()["spec"]["containers"][0]["image"],
+            "dockerhub.example.com/bitnami/nginx:latest"
+        );
+
+        tc.settings.on_host_error = settings::HostErrorPolicy::FailClosed;
+        tc.expected_validation_result = false;
+        let res = tc.eval(validate).unwrap();
+        assert_eq!(
+            res.message.as_deref(),
+            Some(
+                "cannot load the mappings ConfigMap kubewarden/missing: \
+                 not found: configmaps \"missing\" not found"
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn mappings_artifact() -> Result<(), ()> {
+        let manifest = serde_json::json!({
+            "schemaVersion": 2,
+            "annotations": {
+                "mutate-repos-policy.kubewarden.io/repos":
+                    r#"{"docker.io": "mirror.corp/docker", "quay.io": "mirror.corp/quay"}"#,
+                "mutate-repos-policy.kubewarden.io/rules":
+                    r#"[{"source": "gcr.io", "destination": "mirror.corp/gcr"}]"#
+            }
+        });
+        let config_map: apicore::ConfigMap = serde_json::from_value(serde_json::json!({
+            "metadata": {"name": "mirrors", "namespace": "kubewarden"},
+            "data": {"repos": r#"{"docker.io": "local.corp/docker"}"#}
+        }))
+        .unwrap();
+        host::mock(host::MockHost {
+            manifests: HashMap::from([("registry.corp/policies/mirrors:v1".to_string(), manifest)]),
+            config_maps: HashMap::from([("kubewarden/mirrors".to_string(), config_map)]),
+            ..Default::default()
+        });
+        let mut tc = testcase(
+            "Mappings artifact",
+            "test_data/pod_creation.json",
+            true,
+            Settings {
+                mappings_artifact: Some("registry.corp/policies/mirrors:v1".to_string()),
+                ..Default::default()
+            },
+        );
+
+        let res = tc.eval(validate).unwrap();
+        let mutated_object = res.mutated_object.unwrap();
+        assert_eq!(
+            mutated_object["spec"]["containers"][0]["image"],
+            "mirror.corp/docker/bitnami/nginx:latest"
+        );
+        assert_eq!(
+            mutated_object["spec"]["containers"][1]["image"],
+            "mirror.corp/gcr/etcd:3.5.6-0"
+        );
+
+        // the ConfigMap overrides the artifact
+        tc.settings.mappings_config_map = Some(settings::ConfigMapRef {
+            namespace: "kubewarden".to_string(),
+            name: "mirrors".to_string(),
+        });
+        let res = tc.eval(validate).unwrap();
+        assert_eq!(
+            res.mutated_object.unwrap()["spec"]["containers"][0]["image"],
+            "local.corp/docker/bitnami/nginx:latest"
+        );
+
+        // a missing artifact falls back to the settings, unless failing closed
+        tc.settings.mappings_config_map = None;
+        tc.settings.mappings_artifact = Some("registry.corp/policies/missing:v1".to_string());
+        tc.settings.repos = repos(&[("docker.io", "dockerhub.example.com")]);
+        let res = tc.eval(validate).unwrap();
+        assert_eq!(
+            res.mutated_object.unwrap()["spec"]["containers"][0]["image"],
+            "dockerhub.example.com/bitnami/nginx:latest"
+        );
+
+        tc.settings.on_host_error = settings::HostErrorPolicy::FailClosed;
+        tc.expected_validation_result = false;
+        let res = tc.eval(validate).unwrap();
+        assert_eq!(
+            res.message.as_deref(),
+            Some(
+                "cannot load the mappings artifact registry.corp/policies/missing:v1: \
+                 not found: manifest unknown: registry.corp/policies/missing:v1"
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_fields_are_preserved() -> Result<(), ()> {
+        let tc = testcase(
+            "Unknown fields",
+            "test_data/pod_creation_unknown_fields.json",
+            true,
+            Settings {
+                repos: repos(&[("docker.io", "mirror.corp/docker")]),
+                ..Default::default()
+            },
+        );
+
+        let res = tc.eval(validate).unwrap();
+        let mutated_object = res.mutated_object.unwrap();
+        assert_eq!(
+            mutated_object["spec"]["containers"][0],
+            serde_json::json!({
+                "image": "mirror.corp/docker/bitnami/nginx:latest",
+                "name": "nginx",
+                "futureField": {"enabled": true}
+            })
+        );
+        assert_eq!(mutated_object["spec"]["futureSpecField"], "kept");
+        assert!(mutated_object.get("status").is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn mutate_custom_resource_paths() -> Result<(), ()> {
+        let mut tc = testcase(
+            "Custom resource",
+            "test_data/prometheus_creation.json",
+            true,
+            Settings {
+                repos: repos(&[("quay.io", "mirror.corp/quay")]),
+                custom_resources: HashMap::from([(
+                    "monitoring.coreos.com/v1/Prometheus".to_string(),
+                    vec![
+                        ".spec.image".to_string(),
+                        ".spec.containers[*].image".to_string(),
+                    ],
+                )]),
+                ..Default::default()
+            },
+        );
+
+        let res = tc.eval(validate).unwrap();
+        let mutated_object = res.mutated_object.unwrap();
+        assert_eq!(
+            mutated_object["spec"]["image"],
+            "mirror.corp/quay/prometheus/prometheus:v2.48.0"
+        );
+        assert_eq!(
+            mutated_object["spec"]["containers"][0]["image"],
+            "mirror.corp/quay/prometheus-operator/prometheus-config-reloader:v0.70.0"
+        );
+        assert_eq!(mutated_object["spec"]["replicas"], 2);
+        let results: serde_json::Value = serde_json::from_str(
+            &res.audit_annotations.unwrap()["mutate-repos-policy.kubewarden.io/results"],
+        )
+        .unwrap();
+        assert_eq!(results[0]["container"], ".spec.image");
+
+        // kinds missing from the mapping are accepted untouched
+        tc.settings.custom_resources.clear();
+        let res = tc.eval(validate).unwrap();
+        assert!(res.mutated_object.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn accept_without_rules() -> Result<(), ()> {
+        let tc = testcase(
+            "No rules",
+            "test_data/pod_creation.json",
+            true,
+            Settings::default(),
+        );
+
+        let res = tc.eval(validate).unwrap();
+        assert!(res.mutated_object.is_none());
+
         Ok(())
     }
 }
@@ -0,0 +1,878 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use crate::image::ImageRef;
+use crate::settings::{Action, Destination, MatchStrategy, Rule, Settings, Target};
+use crate::{template, LOG_DRAIN};
+
+use regex::Regex;
+use slog::warn;
+
+/// Rewrite rules of the settings compiled into a deterministic list, from
+/// the rules list form of every mapping, see [`Settings::effective_rules`].
+///
+/// Exact rules, looked up by the whole image, take precedence over the
+/// others, which are tried from the highest to the lowest priority. With the
+/// `longestMatch` strategy, regular expression rules of the same priority
+/// are tried first, in the order of the list. Sources, either prefixes or
+/// globs, are then tried from the one with the most components to the one
+/// with the fewest, and from the longest to the shortest among the ones
+/// with as many components. Organization rules are ordered like the source
+/// `*/<org>`. Sources of the same length are tried in this order: the
+/// entries of `rules`, in the order of the list, then the mappings of
+/// `preset`, `baseline` and `repos` merged, sorted by source, then the
+/// organization rules, sorted by organization. The other strategies try the
+/// rules of the same priority in that order regardless of their length. A
+/// rule whose conditions do not hold for an image is skipped in favor of the
+/// next matching one.
+///
+/// The matcher is compiled once per request, and indexes the sources by
+/// their registry, so that only the entries of the registry of an image,
+/// along with the regular expressions and the sources with a glob registry,
+/// are tried.
+pub(crate) struct Matcher {
+    entries: Vec<Entry>,
+    /// Positions of the entries of the sources of every literal registry
+    by_registry: HashMap<String, Vec<usize>>,
+    /// Positions of the entries tried for any registry
+    any_registry: Vec<usize>,
+    /// Positions of the entries of the exact rules, by normalized image
+    exact: HashMap<String, Vec<usize>>,
+}
+
+/// Compiled rewrite rule
+pub(crate) struct Entry {
+    /// Identifier of the rule, e.g. `repos/docker.io`
+    pub rule: String,
+    pattern: Pattern,
+    /// Destination prefix, or replacement of the regular expression
+    pub destination: Destination,
+    /// Compiled tag pattern of the conditions of the destination, if any
+    tag_regex: Option<Regex>,
+}
+
+/// Images a rule applies to
+enum Pattern {
+    /// Images whose leading repository components match the source, either
+    /// a prefix or a glob
+    Source(String),
+    /// Normalized images matching the regular expression
+    Regex(Regex),
+    /// Normalized image equal to the reference, tag or digest included
+    Exact(String),
+    /// Images of the organization, the first component of the repository
+    /// path, from any registry
+    Org(String),
+}
+
+impl Matcher {
+    pub fn new(settings: &Settings) -> Self {
+        let aliases = settings.registry_aliases();
+        let normalize = |src: &str| settings.normalize_source(src, &aliases);
+
+        let mut entries: Vec<Entry> = settings
+            .effective_rules()
+            .into_iter()
+            .filter_map(|(id, rule)| {
+                let regex = rule.regex();
+                match rule {
+                    Rule::Prefix { source, target } => Some(Entry {
+                        rule: id,
+                        pattern: Pattern::Source(normalize(&source)),
+                        destination: Destination::Conditional(Box::new(target)),
+                        tag_regex: None,
+                    }),
+                    Rule::Regex {
+                        replace,
+                        conditions,
+                        attribution,
+                        tag_rewrite,
+                        priority,
+                        ..
+                    } => match regex? {
+                        Ok(regex) => Some(Entry {
+                            rule: id,
+                            pattern: Pattern::Regex(regex),
+                            destination: Destination::Conditional(Box::new(Target {
+                                destination: replace,
+                                conditions,
+                                attribution,
+                                tag_rewrite,
+                                action: Action::Replace,
+                                priority,
+                                failover: Vec::new(),
+                                strip_library_prefix: false,
+                            })),
+                            tag_regex: None,
+                        }),
+                        Err(e) => {
+                            warn!(LOG_DRAIN, "skipping rule"; "error" => e);
+                            None
+                        }
+                    },
+                    Rule::Exact {
+                        exact,
+                        replace,
+                        conditions,
+                        attribution,
+                    } => Some(Entry {
+                        rule: id,
+                        pattern: Pattern::Exact(ImageRef::parse(&normalize(&exact)).to_string()),
+                        destination: Destination::Conditional(Box::new(Target {
+                            destination: replace,
+                            conditions,
+                            attribution,
+                            tag_rewrite: Default::default(),
+                            action: Action::Replace,
+                            priority: 0,
+                            failover: Vec::new(),
+                            strip_library_prefix: false,
+                        })),
+                        tag_regex: None,
+                    }),
+                }
+            })
+            .collect();
+        let mut orgs: Vec<(&String, &Destination)> = settings.orgs.iter().collect();
+        orgs.sort_by_key(|(org, _)| *org);
+        entries.extend(orgs.into_iter().map(|(org, destination)| Entry {
+            rule: format!("orgs/{}", org),
+            pattern: Pattern::Org(org.clone()),
+            destination: destination.clone(),
+            tag_regex: None,
+        }));
+        // tag patterns are compiled once, rules with an invalid one never apply
+        entries.retain_mut(|entry| match entry.destination.tag_regex() {
+            Some(Ok(regex)) => {
+                entry.tag_regex = Some(regex);
+                true
+            }
+            Some(Err(e)) => {
+                warn!(LOG_DRAIN, "skipping rule"; "error" => e);
+                false
+            }
+            None => true,
+        });
+        // stable sort, preserving the order of the sources of the same length
+        let longest = settings.match_strategy == MatchStrategy::LongestMatch;
+        entries.sort_by_key(|entry| {
+            let specificity = match &entry.pattern {
+                Pattern::Source(source) if longest => (source.split('/').count(), source.len()),
+                Pattern::Org(org) if longest => (2, org.len() + 2),
+                _ => (usize::MAX, usize::MAX),
+            };
+            (Reverse(entry.destination.priority()), Reverse(specificity))
+        });
+
+        let mut by_registry: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut any_registry = Vec::new();
+        let mut exact: HashMap<String, Vec<usize>> = HashMap::new();
+        for (position, entry) in entries.iter().enumerate() {
+            match &entry.pattern {
+                Pattern::Source(source) => {
+                    let registry = source.split('/').next().unwrap_or_default();
+                    if registry.contains('*') {
+                        any_registry.push(position);
+                    } else {
+                        by_registry
+                            .entry(registry.to_string())
+                            .or_default()
+                            .push(position);
+                    }
+                }
+                Pattern::Regex(_) | Pattern::Org(_) => any_registry.push(position),
+                Pattern::Exact(image) => exact.entry(image.clone()).or_default().push(position),
+            }
+        }
+
+        Matcher {
+            entries,
+            by_registry,
+            any_registry,
+            exact,
+        }
+    }
+
+    /// Returns the exact rule to apply to `image`, the normalized form of
+    /// `image_ref`, used by a pod running on the `os` operating system
+    pub fn find_exact(&self, image: &str, image_ref: &ImageRef, os: &str) -> Option<&Entry> {
+        self.exact
+            .get(image)?
+            .iter()
+            .map(|position| &self.entries[*position])
+            .find(|entry| {
+                entry
+                    .destination
+                    .applies_to(image_ref, os, entry.tag_regex.as_ref())
+            })
+    }
+
+    /// Returns the rule to apply to `image`, the normalized form of
+    /// `image_ref`, used by a pod running on the `os` operating system
+    pub fn find(&self, image: &str, image_ref: &ImageRef, os: &str) -> Option<&Entry> {
+        self.find_from(0, image, image_ref, os)
+            .map(|(_, entry)| entry)
+    }
+
+    /// Returns every rule applied to `image` with the `all` strategy, each
+    /// one rewriting the image rewritten by the previous ones, along with the
+    /// rewritten image
+    pub fn rewrite_all(&self, image: &str, os: &str) -> (Vec<&Entry>, String) {
+        let mut applied = Vec::new();
+        let mut image = image.to_string();
+        let mut start = 0;
+        while let Some((position, entry)) =
+            self.find_from(start, &image, &ImageRef::parse(&image), os)
+        {
+            image = entry.rewrite(&image);
+            applied.push(entry);
+            start = position + 1;
+        }
+        (applied, image)
+    }
+
+    /// Returns the first rule to apply to `image`, among the ones from the
+    /// `start` position, along with its position
+    fn find_from(
+        &self,
+        start: usize,
+        image: &str,
+        image_ref: &ImageRef,
+        os: &str,
+    ) -> Option<(usize, &Entry)> {
+        // the image string may have a unified Kubernetes registry
+        let unified = ImageRef::parse(image);
+        let registry = unified.components().into_iter().next().unwrap_or_default();
+        let mut candidates: Vec<usize> = self
+            .by_registry
+            .get(&registry)
+            .into_iter()
+            .flatten()
+            .chain(&self.any_registry)
+            .copied()
+            .collect();
+        candidates.sort_unstable();
+        candidates
+            .into_iter()
+            .filter(|position| *position >= start)
+            .map(|position| (position, &self.entries[position]))
+            .find(|(_, entry)| {
+                let matches = match &entry.pattern {
+                    Pattern::Source(source) => unified.match_source(source).is_some(),
+                    Pattern::Regex(regex) => regex.is_match(image),
+                    Pattern::Exact(reference) => reference == image,
+                    Pattern::Org(org) => unified.org() == Some(org.as_str()),
+                };
+                matches
+                    && entry
+                        .destination
+                        .applies_to(image_ref, os, entry.tag_regex.as_ref())
+            })
+    }
+}
+
+impl Entry {
+    /// Returns `image` rewritten by the rule, its tag rewritten first and,
+    /// with `stripLibraryPrefix`, the `library/` component of Docker Hub
+    /// official images dropped. The `stripRegistry` action only drops the
+    /// registry. Templated destinations are rendered with the components of the image,
+    /// either as the whole image when they hold `{image}` or as the prefix
+    /// replacing the matched components.
+    pub fn rewrite(&self, image: &str) -> String {
+        self.rewrite_to(image, self.destination.prefix())
+    }
+
+    /// Returns `image` rewritten by the rule to each of its `failover`
+    /// destinations, in order
+    pub fn failover(&self, image: &str) -> Vec<String> {
+        self.destination
+            .failover()
+            .iter()
+            .map(|destination| self.rewrite_to(image, destination))
+            .collect()
+    }
+
+    /// Returns `image` rewritten by the rule to `destination`
+    fn rewrite_to(&self, image: &str, destination: &str) -> String {
+        let mut image_ref = ImageRef::parse(image);
+        self.destination.retag(&mut image_ref);
+        let mut skip = match &self.pattern {
+            Pattern::Source(source) => image_ref.match_source(source).unwrap_or_default(),
+            // the registry, if any, and the organization
+            Pattern::Org(_) => usize::from(image_ref.registry().is_some()) + 1,
+            Pattern::Regex(regex) => {
+                return regex
+                    .replace(&image_ref.to_string(), destination)
+                    .into_owned()
+            }
+            Pattern::Exact(_) => return destination.to_string(),
+        };
+        if self.destination.strip_library_prefix() && image_ref.is_official() {
+            image_ref.image = image_ref.path_in_org().to_string();
+            // the library component itself may have been matched
+            if skip > 1 {
+                skip -= 1;
+            }
+        }
+        if self.destination.action() == Action::StripRegistry {
+            image_ref.host = None;
+            image_ref.port = None;
+            return image_ref.to_string();
+        }
+        if template::is_image_template(destination) {
+            return template::render(destination, &image_ref);
+        }
+        let prefix = template::render(destination, &image_ref);
+        image_ref.rebase(skip, prefix.trim_end_matches('/'))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(matcher: &Matcher, image: &str) -> Option<String> {
+        let image_ref = ImageRef::parse(image);
+        matcher
+            .find(&image_ref.to_string(), &image_ref, "linux")
+            .map(|entry| entry.rule.clone())
+    }
+
+    #[test]
+    fn longest_prefix_wins() -> Result<(), ()> {
+        let settings = Settings {
+            repos: HashMap::from([
+                ("gcr.io".to_string(), "mirror.corp/gcr".into()),
+                ("k8s.gcr.io".to_string(), "mirror.corp/k8s".into()),
+                ("docker.io".to_string(), "mirror.corp/docker".into()),
+                (
+                    "docker.io/bitnami".to_string(),
+                    "mirror.corp/bitnami".into(),
+                ),
+            ]),
+            ..Default::default()
+        };
+        let matcher = Matcher::new(&settings);
+
+        assert_eq!(
+            rule(&matcher, "k8s.gcr.io/pause:3.9").as_deref(),
+            Some("repos/k8s.gcr.io")
+        );
+        assert_eq!(
+            rule(&matcher, "gcr.io/etcd").as_deref(),
+            Some("repos/gcr.io")
+        );
+        assert_eq!(
+            rule(&matcher, "bitnami/nginx").as_deref(),
+            Some("repos/docker.io/bitnami")
+        );
+        assert_eq!(rule(&matcher, "alpine").as_deref(), Some("repos/docker.io"));
+        assert_eq!(rule(&matcher, "quay.io/foo/bar"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rules_list_order() -> Result<(), ()> {
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "repos": {"quay.io": "mirror.corp/repos"},
+            "rules": [
+                {"source": "ghcr.io", "destination": "mirror.corp/windows", "os": "windows"},
+                {"source": "ghcr.io", "destination": "mirror.corp/first"},
+                {"source": "ghcr.io", "destination": "mirror.corp/second"},
+                {"source": "quay.io", "destination": "mirror.corp/rules", "team": "platform"}
+            ]
+        }))
+        .unwrap();
+        let matcher = Matcher::new(&settings);
+
+        let image_ref = ImageRef::parse("ghcr.io/foo/bar");
+        let entry = matcher
+            .find(&image_ref.to_string(), &image_ref, "linux")
+            .unwrap();
+        assert_eq!(entry.destination.prefix(), "mirror.corp/first");
+        let entry = matcher
+            .find(&image_ref.to_string(), &image_ref, "windows")
+            .unwrap();
+        assert_eq!(entry.destination.prefix(), "mirror.corp/windows");
+
+        // entries of the rules list come before the mappings
+        let image_ref = ImageRef::parse("quay.io/foo/bar");
+        let entry = matcher
+            .find(&image_ref.to_string(), &image_ref, "linux")
+            .unwrap();
+        assert_eq!(entry.rule, "rules/quay.io");
+        assert_eq!(
+            entry.destination.attribution().team.as_deref(),
+            Some("platform")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn repos_map_and_rules_list_are_equivalent() -> Result<(), ()> {
+        let legacy: Settings = serde_json::from_value(serde_json::json!({
+            "repos": {
+                "docker.io": "mirror.corp/docker",
+                "quay.io/foo": {"destination": "mirror.corp/foo", "os": "linux"}
+            }
+        }))
+        .unwrap();
+        let list: Settings = serde_json::from_value(serde_json::json!({
+            "rules": [
+                {"source": "docker.io", "destination": "mirror.corp/docker"},
+                {"source": "quay.io/foo", "destination": "mirror.corp/foo", "os": "linux"}
+            ]
+        }))
+        .unwrap();
+        let rewrite = |settings: &Settings, image: &str| {
+            let image_ref = ImageRef::parse(image);
+            let image = image_ref.to_string();
+            Matcher::new(settings)
+                .find(&image, &image_ref, "linux")
+                .map(|entry| entry.rewrite(&image))
+        };
+
+        for image in ["nginx:1.25", "quay.io/foo/bar@sha256:1234", "quay.io/bar"] {
+            assert_eq!(rewrite(&legacy, image), rewrite(&list, image));
+        }
+        assert_eq!(
+            rewrite(&legacy, "quay.io/foo/bar:v1").as_deref(),
+            Some("mirror.corp/foo/bar:v1")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn strip_registry() -> Result<(), ()> {
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "repos": {
+                "gcr.io": {"action": "stripRegistry"},
+                "docker.io": {"action": "stripRegistry", "stripLibraryPrefix": true},
+                "quay.io": "mirror.corp/quay"
+            }
+        }))
+        .unwrap();
+        let matcher = Matcher::new(&settings);
+        let rewrite = |image: &str| {
+            let image_ref = ImageRef::parse(image);
+            let image = image_ref.to_string();
+            matcher
+                .find(&image, &image_ref, "linux")
+                .map(|entry| entry.rewrite(&image))
+        };
+
+        assert_eq!(rewrite("gcr.io/foo/bar:v1").as_deref(), Some("foo/bar:v1"));
+        assert_eq!(
+            rewrite("gcr.io/foo/bar@sha256:1234").as_deref(),
+            Some("foo/bar@sha256:1234")
+        );
+        assert_eq!(rewrite("nginx:1.25").as_deref(), Some("nginx:1.25"));
+        assert_eq!(
+            rewrite("quay.io/foo/bar:v1").as_deref(),
+            Some("mirror.corp/quay/foo/bar:v1")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn match_strategies() -> Result<(), ()> {
+        let mut settings: Settings = serde_json::from_value(serde_json::json!({
+            "repos": {"docker.io/bitnami": "mirror.corp/bitnami"},
+            "rules": [
+                {"source": "docker.io", "destination": "mirror.corp/docker"},
+                {"match": "^(mirror\\.corp/.*):latest$", "replace": "$1:stable"},
+                {"source": "quay.io", "destination": "mirror.corp/low"},
+                {"source": "quay.io", "destination": "mirror.corp/high", "priority": 10}
+            ]
+        }))
+        .unwrap();
+        let rewrite = |settings: &Settings, image: &str| {
+            let image_ref = ImageRef::parse(image);
+            let image = image_ref.to_string();
+            Matcher::new(settings)
+                .find(&image, &image_ref, "linux")
+                .map(|entry| entry.rewrite(&image))
+        };
+
+        assert_eq!(
+            rewrite(&settings, "bitnami/nginx:1.25").as_deref(),
+            Some("mirror.corp/bitnami/nginx:1.25")
+        );
+        assert_eq!(
+            rewrite(&settings, "quay.io/foo/bar:v1").as_deref(),
+            Some("mirror.corp/high/foo/bar:v1")
+        );
+
+        settings.match_strategy = MatchStrategy::FirstMatch;
+        assert_eq!(
+            rewrite(&settings, "bitnami/nginx:1.25").as_deref(),
+            Some("mirror.corp/docker/bitnami/nginx:1.25")
+        );
+        assert_eq!(
+            rewrite(&settings, "quay.io/foo/bar:v1").as_deref(),
+            Some("mirror.corp/high/foo/bar:v1")
+        );
+
+        settings.match_strategy = MatchStrategy::All;
+        let matcher = Matcher::new(&settings);
+        let (applied, image) = matcher.rewrite_all("docker.io/library/nginx:latest", "linux");
+        assert_eq!(image, "mirror.corp/docker/library/nginx:stable");
+        assert_eq!(applied.len(), 2);
+        let (applied, image) = matcher.rewrite_all("quay.io/foo/bar:v1", "linux");
+        assert_eq!(image, "mirror.corp/high/foo/bar:v1");
+        assert_eq!(applied[0].rule, "rules/quay.io");
+        assert_eq!(applied.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn failover_destinations() -> Result<(), ()> {
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "repos": {"docker.io": {
+                "destination": "eu.mirror.corp/docker",
+                "failover": ["us.mirror.corp/docker", "harbor.corp/{registry}/{image}:{tag}"]
+            }}
+        }))
+        .unwrap();
+        let matcher = Matcher::new(&settings);
+
+        let image_ref = ImageRef::parse("nginx:1.25");
+        let image = image_ref.to_string();
+        let entry = matcher.find(&image, &image_ref, "linux").unwrap();
+        assert_eq!(
+            entry.rewrite(&image),
+            "eu.mirror.corp/docker/library/nginx:1.25"
+        );
+        assert_eq!(
+            entry.failover(&image),
+            [
+                "us.mirror.corp/docker/library/nginx:1.25",
+                "harbor.corp/docker.io/library/nginx:1.25"
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn exact_rules() -> Result<(), ()> {
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "repos": {"k8s.gcr.io": "mirror.corp/k8s"},
+            "rules": [
+                {"match": "^k8s\\.gcr\\.io/(.*)$", "replace": "mirror.corp/regex/$1"},
+                {
+                    "exact": "k8s.gcr.io/pause:3.9",
+                    "replace": "registry.corp/infra/pause:3.9-windows",
+                    "os": "windows"
+                },
+                {"exact": "k8s.gcr.io/pause:3.9", "replace": "registry.corp/infra/pause:3.9-patched"},
+                {"exact": "docker.io/library/nginx:1.25", "replace": "registry.corp/nginx:1.25-fips"}
+            ]
+        }))
+        .unwrap();
+        let matcher = Matcher::new(&settings);
+        let rewrite = |image: &str, os: &str| {
+            let image_ref = ImageRef::parse(image);
+            let image = image_ref.to_string();
+            let entry = matcher.find_exact(&image, &image_ref, os)?;
+            Some((entry.rule.clone(), entry.rewrite(&image)))
+        };
+
+        assert_eq!(
+            rewrite("k8s.gcr.io/pause:3.9", "linux"),
+            Some((
+                "rules/k8s.gcr.io/pause:3.9".to_string(),
+                "registry.corp/infra/pause:3.9-patched".to_string()
+            ))
+        );
+        assert_eq!(
+            rewrite("k8s.gcr.io/pause:3.9", "windows").unwrap().1,
+            "registry.corp/infra/pause:3.9-windows"
+        );
+        // the normalized image is looked up
+        assert_eq!(
+            rewrite("nginx:1.25", "linux").unwrap().1,
+            "registry.corp/nginx:1.25-fips"
+        );
+        assert_eq!(rewrite("k8s.gcr.io/pause:3.8", "linux"), None);
+
+        // exact rules are never tried by the other lookups
+        let image_ref = ImageRef::parse("k8s.gcr.io/pause:3.9");
+        let entry = matcher
+            .find(&image_ref.to_string(), &image_ref, "linux")
+            .unwrap();
+        assert_eq!(entry.rule, "rules/^k8s\\.gcr\\.io/(.*)$");
+
+        Ok(())
+    }
+
+    #[test]
+    fn regex_rules() -> Result<(), ()> {
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "repos": {"quay.io/foo": "mirror.corp/foo"},
+            "rules": [
+                {"source": "quay.io", "destination": "mirror.corp/prefix"},
+                {"match": "^quay\\.io/(.*)$", "replace": "mirror.corp/quay/$1"}
+            ]
+        }))
+        .unwrap();
+        let matcher = Matcher::new(&settings);
+
+        let image_ref = ImageRef::parse("quay.io/foo/bar:v1");
+        let entry = matcher
+            .find(&image_ref.to_string(), &image_ref, "linux")
+            .unwrap();
+        assert_eq!(entry.rule, "rules/^quay\\.io/(.*)$");
+        assert_eq!(
+            entry.rewrite(&image_ref.to_string()),
+            "mirror.corp/quay/foo/bar:v1"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn glob_rules() -> Result<(), ()> {
+        let settings = Settings {
+            repos: HashMap::from([
+                ("*.gcr.io".to_string(), "mirror.corp/gcr".into()),
+                ("k8s.gcr.io".to_string(), "mirror.corp/k8s".into()),
+                (
+                    "docker.io/bitnami/*".to_string(),
+                    "mirror.corp/bitnami".into(),
+                ),
+            ]),
+            ..Default::default()
+        };
+        let matcher = Matcher::new(&settings);
+        let rewrite = |image: &str| {
+            let image_ref = ImageRef::parse(image);
+            let image = image_ref.to_string();
+            matcher
+                .find(&image, &image_ref, "linux")
+                .map(|entry| entry.rewrite(&image))
+        };
+
+        assert_eq!(
+            rewrite("eu.gcr.io/google/etcd:3.5").as_deref(),
+            Some("mirror.corp/gcr/google/etcd:3.5")
+        );
+        assert_eq!(
+            rewrite("k8s.gcr.io/pause:3.9").as_deref(),
+            Some("mirror.corp/k8s/pause:3.9")
+        );
+        assert_eq!(
+            rewrite("bitnami/nginx:1.25").as_deref(),
+            Some("mirror.corp/bitnami/nginx:1.25")
+        );
+        assert_eq!(rewrite("gcr.io/etcd"), None);
+        assert_eq!(rewrite("docker.io/bitnamilabs/nginx"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn registry_index() -> Result<(), ()> {
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "repos": {
+                "localhost:5000/team": "mirror.corp/team",
+                "*.gcr.io/google": "mirror.corp/google",
+                "eu.gcr.io": "mirror.corp/eu"
+            },
+            "rules": [{"match": "^eu\\.gcr\\.io/legacy/(.*)$", "replace": "mirror.corp/legacy/$1"}]
+        }))
+        .unwrap();
+        let matcher = Matcher::new(&settings);
+
+        assert_eq!(
+            rule(&matcher, "localhost:5000/team/app").as_deref(),
+            Some("repos/localhost:5000/team")
+        );
+        assert_eq!(rule(&matcher, "localhost:5001/team/app"), None);
+        // the longer glob source wins over the literal registry
+        assert_eq!(
+            rule(&matcher, "eu.gcr.io/google/etcd").as_deref(),
+            Some("repos/*.gcr.io/google")
+        );
+        assert_eq!(
+            rule(&matcher, "eu.gcr.io/foo/bar").as_deref(),
+            Some("repos/eu.gcr.io")
+        );
+        assert_eq!(
+            rule(&matcher, "eu.gcr.io/legacy/bar").as_deref(),
+            Some("rules/^eu\\.gcr\\.io/legacy/(.*)$")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn sources_match_whole_components() -> Result<(), ()> {
+        let settings = Settings {
+            repos: HashMap::from([
+                ("docker.io/foo".to_string(), "mirror.corp/foo".into()),
+                ("docker.io".to_string(), "mirror.corp/docker".into()),
+            ]),
+            ..Default::default()
+        };
+        let matcher = Matcher::new(&settings);
+        let rewrite = |image: &str| {
+            let image_ref = ImageRef::parse(image);
+            let image = image_ref.to_string();
+            matcher
+                .find(&image, &image_ref, "linux")
+                .map(|entry| entry.rewrite(&image))
+        };
+
+        assert_eq!(
+            rewrite("foo/app:v1").as_deref(),
+            Some("mirror.corp/foo/app:v1")
+        );
+        assert_eq!(
+            rewrite("foobar/app:v1").as_deref(),
+            Some("mirror.corp/docker/foobar/app:v1")
+        );
+        // only the prefix is replaced
+        assert_eq!(
+            rewrite("docker.io/foo/docker.io:v1").as_deref(),
+            Some("mirror.corp/foo/docker.io:v1")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn templated_destinations() -> Result<(), ()> {
+        let settings = Settings {
+            repos: HashMap::from([
+                (
+                    "*.gcr.io".to_string(),
+                    "harbor.corp/{registry}/{image}:{tag}@{digest}".into(),
+                ),
+                ("quay.io".to_string(), "harbor.corp/{registry}".into()),
+            ]),
+            ..Default::default()
+        };
+        let matcher = Matcher::new(&settings);
+        let rewrite = |image: &str| {
+            let image_ref = ImageRef::parse(image);
+            let image = image_ref.to_string();
+            matcher
+                .find(&image, &image_ref, "linux")
+                .map(|entry| entry.rewrite(&image))
+        };
+
+        assert_eq!(
+            rewrite("eu.gcr.io/google/etcd:3.5").as_deref(),
+            Some("harbor.corp/eu.gcr.io/google/etcd:3.5")
+        );
+        assert_eq!(
+            rewrite("us.gcr.io/pause@sha256:1234").as_deref(),
+            Some("harbor.corp/us.gcr.io/pause@sha256:1234")
+        );
+        assert_eq!(
+            rewrite("quay.io/foo/bar:v1").as_deref(),
+            Some("harbor.corp/quay.io/foo/bar:v1")
+        );
+        assert_eq!(
+            rewrite("eu.gcr.io/google/etcd:3.5@sha256:1234").as_deref(),
+            Some("harbor.corp/eu.gcr.io/google/etcd:3.5@sha256:1234")
+        );
+        assert_eq!(
+            rewrite("quay.io/foo/bar:v1@sha256:1234").as_deref(),
+            Some("harbor.corp/quay.io/foo/bar:v1@sha256:1234")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn tag_rewriting_rules() -> Result<(), ()> {
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "repos": {"quay.io": {"destination": "mirror.corp/quay", "tagSuffix": "-mirrored"}},
+            "rules": [
+                {"source": "docker.io/library", "destination": "mirror.corp/library", "tags": {"latest": "stable"}},
+                {"match": "^ghcr\\.io/(.*)$", "replace": "mirror.corp/ghcr/$1", "tagPrefix": "v"}
+            ]
+        }))
+        .unwrap();
+        let matcher = Matcher::new(&settings);
+        let rewrite = |image: &str| {
+            let image_ref = ImageRef::parse(image);
+            let image = image_ref.to_string();
+            matcher
+                .find(&image, &image_ref, "linux")
+                .map(|entry| entry.rewrite(&image))
+        };
+
+        assert_eq!(
+            rewrite("nginx").as_deref(),
+            Some("mirror.corp/library/nginx:stable")
+        );
+        assert_eq!(
+            rewrite("nginx:1.25").as_deref(),
+            Some("mirror.corp/library/nginx:1.25")
+        );
+        assert_eq!(
+            rewrite("quay.io/foo/bar:v1").as_deref(),
+            Some("mirror.corp/quay/foo/bar:v1-mirrored")
+        );
+        assert_eq!(
+            rewrite("ghcr.io/foo/bar:1.0").as_deref(),
+            Some("mirror.corp/ghcr/foo/bar:v1.0")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn strip_library_prefix() -> Result<(), ()> {
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "repos": {
+                "docker.io": {"destination": "harbor.corp/dockerhub", "stripLibraryPrefix": true},
+                "docker.io/library/busybox": {"destination": "harbor.corp/tools", "stripLibraryPrefix": true},
+                "ghcr.io": {"destination": "harbor.corp/{image}:{tag}", "stripLibraryPrefix": true}
+            },
+            "rules": [
+                {"source": "docker.io/library", "destination": "mirror.corp/official", "stripLibraryPrefix": true, "os": "windows"}
+            ]
+        }))
+        .unwrap();
+        let matcher = Matcher::new(&settings);
+        let rewrite = |image: &str, os: &str| {
+            let image_ref = ImageRef::parse(image);
+            let image = image_ref.to_string();
+            matcher
+                .find(&image, &image_ref, os)
+                .map(|entry| entry.rewrite(&image))
+        };
+
+        assert_eq!(
+            rewrite("alpine:3.19", "linux").as_deref(),
+            Some("harbor.corp/dockerhub/alpine:3.19")
+        );
+        assert_eq!(
+            rewrite("bitnami/nginx:1.25", "linux").as_deref(),
+            Some("harbor.corp/dockerhub/bitnami/nginx:1.25")
+        );
+        assert_eq!(
+            rewrite("busybox:1.36", "linux").as_deref(),
+            Some("harbor.corp/tools:1.36")
+        );
+        assert_eq!(
+            rewrite("alpine:3.19", "windows").as_deref(),
+            Some("mirror.corp/official/alpine:3.19")
+        );
+        assert_eq!(
+            rewrite("ghcr.io/library/app:v1", "linux").as_deref(),
+            Some("harbor.corp/library/app:v1")
+        );
+
+        Ok(())
+    }
+}
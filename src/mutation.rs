@@ -0,0 +1,2263 @@
+use k8s_openapi::api::core::v1 as apicore;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::host::{self, HostError};
+use crate::image::{self, ImageRef};
+use crate::jsonpath;
+use crate::matcher::Matcher;
+use crate::report::{Finding, Reason};
+use crate::settings::{
+    Attribution, HostErrorPolicy, MatchStrategy, MissingPolicy, NameFilter, Settings, Signature,
+};
+use crate::workload::Workload;
+use crate::{ANNOTATION_PREFIX, LOG_DRAIN};
+
+use slog::{info, warn};
+
+/// Label, and node selector, holding the operating system of a node
+const OS_LABEL: &str = "kubernetes.io/os";
+
+/// Operating system of the pods setting none, and of custom resources
+pub(crate) const DEFAULT_OS: &str = "linux";
+
+/// Mutation of the images of a single pod
+pub(crate) struct Mutation<'a> {
+    settings: &'a Settings,
+    /// Rewrite rules of every settings layer, compiled
+    matcher: Matcher,
+    /// Registries admitted without matching any rule
+    passthrough: Vec<String>,
+    /// Prefixes of the destinations, whose images are already mirrored
+    mirrored: Vec<String>,
+    /// Registry aliases, replaced by the registry they serve
+    aliases: HashMap<String, String>,
+    /// Patterns of the images never rewritten, normalized like the sources
+    excluded: Vec<String>,
+    /// Operating system of the pod
+    os: String,
+    /// Destinations missing at the mirror, to be replicated
+    requested: Vec<String>,
+    /// Results of the evaluation of every image
+    findings: Vec<Finding>,
+    /// Original images of the rewritten containers and volumes, keyed by the
+    /// field of the pod spec listing them and their name, e.g.
+    /// `volumes/model`, recorded with `recordOriginalImage`
+    originals: BTreeMap<String, String>,
+    /// Images rewritten to short names, e.g. by `stripRegistry`, by this or
+    /// a previous admission of the pod template, which must be left
+    /// untouched as they would now be matched as Docker Hub images
+    stripped: Vec<String>,
+}
+
+/// Image rewritten by a rule
+struct Rewrite {
+    /// Identifier of the rule, e.g. `repos/docker.io`
+    rule: String,
+    image: String,
+    /// Image rewritten to the failover destinations of the rule, in order
+    failover: Vec<String>,
+    attribution: Attribution,
+}
+
+/// Returns the operating system of a pod, taken from `spec.os.name` or the
+/// `kubernetes.io/os` node selector, and defaulting to `linux`
+fn pod_os(pod_spec: &apicore::PodSpec) -> String {
+    pod_spec
+        .os
+        .as_ref()
+        .map(|os| os.name.clone())
+        .or_else(|| {
+            pod_spec
+                .node_selector
+                .as_ref()
+                .and_then(|selector| selector.get(OS_LABEL).cloned())
+        })
+        .unwrap_or_else(|| DEFAULT_OS.to_string())
+}
+
+/// Rewrites the images of the pods run by a workload according to
+/// `settings`, returning the mutated workload along with the results of the
+/// evaluation of its images
+pub(crate) fn mutate_workload<W: Workload>(
+    mut workload: W,
+    settings: &Settings,
+) -> Result<(W, Vec<Finding>), Box<Finding>> {
+    // status and managedFields are owned by the API server, never echo them
+    // back in the mutated object
+    workload.clear_status();
+    workload.metadata_mut().managed_fields = None;
+
+    let Some((metadata, pod_spec)) = workload.pod_mut() else {
+        return Ok((workload, Vec::new()));
+    };
+    let mut mutation = Mutation::new(settings, pod_os(pod_spec));
+    if settings.excluded_os.contains(&mutation.os) {
+        info!(LOG_DRAIN, "pod operating system is excluded, skipping"; "os" => &mutation.os);
+        return Ok((workload, Vec::new()));
+    }
+    mutation.stripped = stripped_images(metadata);
+
+    let filters = &settings.container_filters;
+    if settings.mutate_containers {
+        pod_spec.containers =
+            mutation.mutate_containers("containers", &pod_spec.containers, &filters.containers)?;
+    }
+    if let Some(init_containers) = pod_spec
+        .init_containers
+        .as_ref()
+        .filter(|_| settings.mutate_init_containers)
+    {
+        pod_spec.init_containers = Some(mutation.mutate_containers(
+            "initContainers",
+            init_containers,
+            &filters.init_containers,
+        )?);
+    }
+    if let Some(volumes) = &pod_spec.volumes {
+        pod_spec.volumes = Some(mutation.mutate_volumes(volumes)?);
+    }
+    if let Some(ephemeral_containers) = pod_spec
+        .ephemeral_containers
+        .as_ref()
+        .filter(|_| settings.mutate_ephemeral_containers)
+    {
+        pod_spec.ephemeral_containers =
+            Some(mutation.mutate_ephemeral_containers(ephemeral_containers, &[])?);
+    }
+
+    mutation.mutate_annotations(metadata)?;
+
+    add_pull_secrets(pod_spec, &mutation.findings, settings);
+
+    annotate(metadata, &mutation);
+    Ok((workload, mutation.findings))
+}
+
+/// Rewrites the images found at the JSON `paths` of the custom resource
+/// `object`, returning the results of the evaluation of its images
+pub(crate) fn mutate_custom_resource(
+    object: &mut serde_json::Value,
+    paths: &[String],
+    settings: &Settings,
+) -> Result<Vec<Finding>, Box<Finding>> {
+    let mut metadata: ObjectMeta =
+        serde_json::from_value(object["metadata"].clone()).unwrap_or_default();
+    let mut mutation = Mutation::new(settings, DEFAULT_OS.to_string());
+    mutation.stripped = stripped_images(&metadata);
+    for path in paths {
+        let segments = match jsonpath::parse(path) {
+            Ok(segments) => segments,
+            Err(e) => {
+                warn!(LOG_DRAIN, "skipping JSON path"; "error" => e);
+                continue;
+            }
+        };
+        jsonpath::visit_strings::<Box<Finding>>(object, &segments, "", &mut |location, image| {
+            if let Some(rewritten) = mutation.mutate_image(location, image)? {
+                mutation
+                    .originals
+                    .insert(location.to_string(), image.clone());
+                *image = rewritten;
+            }
+            Ok(())
+        })?;
+    }
+
+    let annotations = metadata.annotations.clone();
+    annotate(&mut metadata, &mutation);
+    if metadata.annotations != annotations {
+        object["metadata"]["annotations"] = serde_json::json!(metadata.annotations);
+    }
+    Ok(mutation.findings)
+}
+
+/// Returns the images found at the JSON `paths` of the custom resource
+/// `object`
+pub(crate) fn custom_resource_images(object: &serde_json::Value, paths: &[String]) -> Vec<String> {
+    let mut object = object.clone();
+    let mut images = Vec::new();
+    for segments in paths.iter().filter_map(|path| jsonpath::parse(path).ok()) {
+        let _ = jsonpath::visit_strings::<()>(&mut object, &segments, "", &mut |_, image| {
+            images.push(image.clone());
+            Ok(())
+        });
+    }
+    images
+}
+
+/// Returns the annotation listing the images rewritten to short names
+fn stripped_annotation() -> String {
+    format!("{}/stripped-images", ANNOTATION_PREFIX)
+}
+
+/// Returns the images rewritten to short names by a previous admission of
+/// the object, or of the pod template it was created from
+fn stripped_images(metadata: &ObjectMeta) -> Vec<String> {
+    metadata
+        .annotations
+        .as_ref()
+        .and_then(|annotations| annotations.get(&stripped_annotation()))
+        .map(|images| images.split(',').map(String::from).collect())
+        .unwrap_or_default()
+}
+
+/// Annotates the metadata of the mutated object with the images to
+/// replicate, the images rewritten to short names and, with
+/// `recordMutationSummary`, the rewritten images, and with
+/// `recordOriginalImage`, the original images
+fn annotate(metadata: &mut ObjectMeta, mutation: &Mutation) {
+    let mut requested = mutation.requested.clone();
+    if !requested.is_empty() {
+        requested.sort();
+        requested.dedup();
+        metadata
+            .annotations
+            .get_or_insert_with(BTreeMap::new)
+            .insert(
+                format!("{}/mirror-requested", ANNOTATION_PREFIX),
+                requested.join(","),
+            );
+    }
+    let mut stripped = mutation.stripped.clone();
+    if !stripped.is_empty() {
+        stripped.sort();
+        stripped.dedup();
+        metadata
+            .annotations
+            .get_or_insert_with(BTreeMap::new)
+            .insert(stripped_annotation(), stripped.join(","));
+    }
+    if mutation.settings.record_mutation_summary {
+        let summary: Vec<String> = mutation
+            .findings
+            .iter()
+            .filter(|finding| finding.rewritten.is_some())
+            .map(Finding::to_string)
+            .collect();
+        if !summary.is_empty() {
+            metadata
+                .annotations
+                .get_or_insert_with(BTreeMap::new)
+                .insert(
+                    format!("{}/mutation-summary", ANNOTATION_PREFIX),
+                    summary.join("; "),
+                );
+        }
+    }
+    if mutation.settings.record_original_image {
+        let originals = &mutation.originals;
+        if !originals.is_empty() {
+            metadata
+                .annotations
+                .get_or_insert_with(BTreeMap::new)
+                .insert(
+                    format!("{}/original-images", ANNOTATION_PREFIX),
+                    serde_json::to_string(originals).unwrap_or_default(),
+                );
+        }
+    }
+}
+
+/// Rewrites the images of the ephemeral containers added to `pod` by an
+/// update of its `pods/ephemeralcontainers` subresource. Such an update
+/// cannot change any other field of the pod, nor the ephemeral containers
+/// named in `existing`, which were already there.
+pub(crate) fn mutate_ephemeral_containers(
+    mut pod: apicore::Pod,
+    existing: &[String],
+    settings: &Settings,
+) -> Result<(apicore::Pod, Vec<Finding>), Box<Finding>> {
+    pod.status = None;
+    pod.metadata.managed_fields = None;
+
+    let Some(pod_spec) = pod.spec.as_mut() else {
+        return Ok((pod, Vec::new()));
+    };
+    let mut mutation = Mutation::new(settings, pod_os(pod_spec));
+    if settings.excluded_os.contains(&mutation.os) {
+        info!(LOG_DRAIN, "pod operating system is excluded, skipping"; "os" => &mutation.os);
+        return Ok((pod, Vec::new()));
+    }
+    mutation.stripped = stripped_images(&pod.metadata);
+    if let Some(ephemeral_containers) = pod_spec
+        .ephemeral_containers
+        .as_ref()
+        .filter(|_| settings.mutate_ephemeral_containers)
+    {
+        pod_spec.ephemeral_containers =
+            Some(mutation.mutate_ephemeral_containers(ephemeral_containers, existing)?);
+    }
+    if !mutation.requested.is_empty() {
+        // the pod annotations cannot be changed through the subresource
+        info!(LOG_DRAIN, "mirror replication needed"; "images" => mutation.requested.join(","));
+    }
+    Ok((pod, mutation.findings))
+}
+
+/// Appends the pull secrets of the registries the images have been rewritten
+/// to, according to the `pullSecrets` mapping, to the pull secrets of the pod
+fn add_pull_secrets(pod_spec: &mut apicore::PodSpec, findings: &[Finding], settings: &Settings) {
+    for finding in findings {
+        if finding.reason != Reason::Rewritten {
+            continue;
+        }
+        let Some(registry) = finding
+            .rewritten
+            .as_ref()
+            .and_then(|image| ImageRef::parse(image).registry())
+        else {
+            continue;
+        };
+        let Some(secret) = settings.pull_secrets.get(&registry) else {
+            continue;
+        };
+        let secrets = pod_spec.image_pull_secrets.get_or_insert_with(Vec::new);
+        if !secrets.iter().any(|s| &s.name == secret) {
+            secrets.push(apicore::LocalObjectReference {
+                name: secret.clone(),
+            });
+        }
+    }
+}
+
+/// Looks an image up at its registry, using the OCI host capability, and
+/// returns the digest of its manifest
+fn lookup_image(image: &str) -> Result<String, HostError> {
+    host::with_host(|host| host.manifest_digest(image))
+}
+
+/// Verifies the sigstore `signature` of an image, using the verification
+/// host capability
+fn lookup_signature(image: &str, signature: &Signature) -> Result<bool, HostError> {
+    host::with_host(|host| host.verify_signature(image, signature))
+}
+
+/// Returns `image` referenced by `digest` instead of its mutable tag, images
+/// already referenced by digest being left untouched
+fn pin_digest(image: &str, digest: &str) -> String {
+    let image_ref = ImageRef::parse(image);
+    if image_ref.hash.is_some() {
+        return image.to_string();
+    }
+    format!("{}@{}", image_ref.repository(), digest)
+}
+
+impl<'a> Mutation<'a> {
+    pub fn new(settings: &'a Settings, os: String) -> Self {
+        Mutation {
+            settings,
+            matcher: Matcher::new(settings),
+            passthrough: settings.passthrough_registries(),
+            mirrored: settings.destination_prefixes(),
+            aliases: settings.registry_aliases(),
+            excluded: settings.excluded_images(),
+            os,
+            requested: Vec::new(),
+            findings: Vec::new(),
+            originals: BTreeMap::new(),
+            stripped: Vec::new(),
+        }
+    }
+
+    /// Rewrites the images of the `containers` allowed by `filter`, listed by
+    /// the `field` of the pod spec
+    fn mutate_containers(
+        &mut self,
+        field: &str,
+        containers: &[apicore::Container],
+        filter: &NameFilter,
+    ) -> Result<Vec<apicore::Container>, Box<Finding>> {
+        containers
+            .iter()
+            .map(|container| {
+                let mut ctr = container.clone();
+                if !filter.allows(&ctr.name) {
+                    return Ok(ctr);
+                }
+                self.mutate_references(&ctr.name, ctr.env.as_mut(), ctr.args.as_mut())?;
+                if let Some(ctr_image) = &ctr.image {
+                    if let Some(image) = self.mutate_image(&ctr.name, ctr_image)? {
+                        self.originals
+                            .insert(format!("{}/{}", field, ctr.name), ctr_image.clone());
+                        self.inject_original_image(&mut ctr.env, ctr_image);
+                        ctr.image = Some(image);
+                        self.set_pull_policy(&mut ctr.image_pull_policy);
+                    }
+                }
+                Ok(ctr)
+            })
+            .collect()
+    }
+
+    /// Rewrites the images of ephemeral containers, added by `kubectl debug`
+    /// through the `pods/ephemeralcontainers` subresource. Images found in
+    /// the `debugImages` mapping are replaced by the approved image, checked
+    /// like any rewritten image unless excluded by `excludeImages`, the
+    /// others are rewritten like the images of regular containers. The
+    /// containers named in `existing` are immutable and left untouched, as
+    /// well as the ones excluded by the ephemeral containers filter.
+    fn mutate_ephemeral_containers(
+        &mut self,
+        containers: &[apicore::EphemeralContainer],
+        existing: &[String],
+    ) -> Result<Vec<apicore::EphemeralContainer>, Box<Finding>> {
+        containers
+            .iter()
+            .map(|container| {
+                let mut ctr = container.clone();
+                let filter = &self.settings.container_filters.ephemeral_containers;
+                if existing.contains(&ctr.name) || !filter.allows(&ctr.name) {
+                    return Ok(ctr);
+                }
+                self.mutate_references(&ctr.name, ctr.env.as_mut(), ctr.args.as_mut())?;
+                let Some(ctr_image) = &ctr.image else {
+                    return Ok(ctr);
+                };
+                let image_ref = ImageRef::parse(ctr_image);
+                let repository = image_ref.repository();
+                let debug_image = self
+                    .settings
+                    .debug_images
+                    .iter()
+                    .find(|(src, _)| ImageRef::parse(src).repository() == repository);
+                let image = match debug_image {
+                    Some(_) if self.is_excluded(&image_ref) => {
+                        info!(LOG_DRAIN, "image excluded from rewriting, skipping";
+                            "name" => &ctr.name, "image" => ctr_image);
+                        None
+                    }
+                    Some((src, dest)) => {
+                        let rewrite = Rewrite {
+                            rule: format!("debugImages/{}", src),
+                            image: dest.clone(),
+                            failover: Vec::new(),
+                            attribution: Attribution::default(),
+                        };
+                        self.apply_rewrite(&ctr.name, ctr_image, rewrite)?
+                    }
+                    None => self.mutate_image(&ctr.name, ctr_image)?,
+                };
+                if let Some(image) = image {
+                    self.originals.insert(
+                        format!("ephemeralContainers/{}", ctr.name),
+                        ctr_image.clone(),
+                    );
+                    self.inject_original_image(&mut ctr.env, ctr_image);
+                    ctr.image = Some(image);
+                    self.set_pull_policy(&mut ctr.image_pull_policy);
+                }
+                Ok(ctr)
+            })
+            .collect()
+    }
+
+    /// Determines if an image matches one of the `excludeImages` patterns
+    fn is_excluded(&self, image_ref: &ImageRef) -> bool {
+        let mut image = image_ref.to_string();
+        if self.settings.unify_k8s_registries {
+            image = image::unify_k8s_registry(&image);
+        }
+        let unified = ImageRef::parse(&image);
+        self.excluded.iter().any(|pattern| {
+            if image::is_whole_reference(pattern) {
+                ImageRef::parse(pattern).to_string() == image
+            } else {
+                unified.match_source(pattern).is_some()
+            }
+        })
+    }
+
+    /// Sets the pull policy of a container whose image has been rewritten,
+    /// with `setImagePullPolicy`
+    fn set_pull_policy(&self, pull_policy: &mut Option<String>) {
+        if let Some(policy) = &self.settings.set_image_pull_policy {
+            *pull_policy = Some(policy.clone());
+        }
+    }
+
+    /// Adds the `original` image of a container whose image is rewritten to
+    /// its environment, with `injectOriginalImage`, unless the variable is
+    /// already set, e.g. by a previous admission of the same pod
+    fn inject_original_image(&self, env: &mut Option<Vec<apicore::EnvVar>>, original: &str) {
+        if !self.settings.inject_original_image {
+            return;
+        }
+        let name = self.settings.original_image_env_var();
+        let env = env.get_or_insert_with(Vec::new);
+        if env.iter().all(|var| var.name != name) {
+            env.push(apicore::EnvVar {
+                name: name.to_string(),
+                value: Some(original.to_string()),
+                value_from: None,
+            });
+        }
+    }
+
+    /// Rewrites the images held by the environment variables and the
+    /// arguments of the container `name`, selected by `imageEnvVars` and
+    /// `imageArgs`. Arguments hold an image either after the `=` of a
+    /// matching flag, e.g. `--sidecar-image=nginx`, or right after the flag.
+    /// Values that are not image references are left untouched.
+    fn mutate_references(
+        &mut self,
+        name: &str,
+        env: Option<&mut Vec<apicore::EnvVar>>,
+        args: Option<&mut Vec<String>>,
+    ) -> Result<(), Box<Finding>> {
+        let settings = self.settings;
+        let matches = |globs: &[String], name: &str| {
+            globs.iter().any(|glob| image::wildcard_match(glob, name))
+        };
+        for var in env.into_iter().flatten() {
+            if !matches(&settings.image_env_vars, &var.name) {
+                continue;
+            }
+            if let Some(value) = &var.value {
+                let location = format!("{}/env/{}", name, var.name);
+                if let Some(image) = self.mutate_reference(&location, value)? {
+                    var.value = Some(image);
+                }
+            }
+        }
+        let Some(args) = args else {
+            return Ok(());
+        };
+        let mut position = 0;
+        while position < args.len() {
+            let location = format!("{}/args[{}]", name, position);
+            if let Some((flag, value)) = args[position].split_once('=') {
+                if matches(&settings.image_args, flag) {
+                    if let Some(image) = self.mutate_reference(&location, value)? {
+                        args[position] = format!("{}={}", flag, image);
+                    }
+                }
+            } else if matches(&settings.image_args, &args[position]) && position + 1 < args.len() {
+                position += 1;
+                let location = format!("{}/args[{}]", name, position);
+                if let Some(image) = self.mutate_reference(&location, &args[position])? {
+                    args[position] = image;
+                }
+            }
+            position += 1;
+        }
+        Ok(())
+    }
+
+    /// Rewrites the images held by the pod annotations selected by
+    /// `imageAnnotations`, e.g. the proxy image of a sidecar injector
+    fn mutate_annotations(&mut self, metadata: &mut ObjectMeta) -> Result<(), Box<Finding>> {
+        let globs = &self.settings.image_annotations;
+        for (key, value) in metadata.annotations.iter_mut().flatten() {
+            if !globs.iter().any(|glob| image::wildcard_match(glob, key)) {
+                continue;
+            }
+            let location = format!("annotations/{}", key);
+            if let Some(image) = self.mutate_reference(&location, value)? {
+                *value = image;
+            }
+        }
+        Ok(())
+    }
+
+    /// Rewrites an image held by an environment variable, an argument or an
+    /// annotation, if the value is an image reference at all
+    fn mutate_reference(
+        &mut self,
+        location: &str,
+        value: &str,
+    ) -> Result<Option<String>, Box<Finding>> {
+        if image::has_template_placeholder(value) || ImageRef::try_parse(value).is_err() {
+            return Ok(None);
+        }
+        self.mutate_image(location, value)
+    }
+
+    /// Rewrites the references of the OCI image volume sources of `volumes`
+    fn mutate_volumes(
+        &mut self,
+        volumes: &[apicore::Volume],
+    ) -> Result<Vec<apicore::Volume>, Box<Finding>> {
+        volumes
+            .iter()
+            .map(|volume| {
+                let mut vol = volume.clone();
+                if let Some(reference) = vol.image.as_ref().and_then(|i| i.reference.as_ref()) {
+                    if let Some(image) = self.mutate_image(&vol.name, reference)? {
+                        self.originals
+                            .insert(format!("volumes/{}", vol.name), reference.clone());
+                        vol.image.as_mut().unwrap().reference = Some(image);
+                    }
+                }
+                Ok(vol)
+            })
+            .collect()
+    }
+
+    /// Returns the mirrored image to use in place of `image`, used by the
+    /// container or volume `name`, if any. Destinations that cannot be found
+    /// at the mirror when `requestReplication` is enabled are recorded to be
+    /// replicated, and the original image is kept, as when
+    /// `verifyDestinationExists` falls back. With `pinDigest`, the
+    /// destination is referenced by the digest of its manifest.
+    pub fn mutate_image(
+        &mut self,
+        name: &str,
+        image: &str,
+    ) -> Result<Option<String>, Box<Finding>> {
+        let mut finding = Finding {
+            rule: None,
+            container: name.to_string(),
+            original: image.to_string(),
+            rewritten: None,
+            reason: Reason::Rewritten,
+            attribution: Attribution::default(),
+        };
+        if self.stripped.iter().any(|stripped| stripped == image) {
+            // e.g. a pod created from a template whose registry was stripped
+            info!(LOG_DRAIN, "image rewritten to a short name, skipping";
+                "name" => name, "image" => image);
+            return Ok(None);
+        }
+        if image::has_template_placeholder(image) {
+            finding.reason = Reason::TemplatePlaceholder;
+            if self.settings.reject_templated_images {
+                return Err(Box::new(finding));
+            }
+            warn!(LOG_DRAIN, "unresolved template placeholder in image, skipping";
+                "name" => name, "image" => image);
+            self.findings.push(finding);
+            return Ok(None);
+        }
+        let unaliased = image::unalias_registry(image, &self.aliases);
+        let parsed = if self.settings.strict_image_references {
+            ImageRef::try_parse_strict(&unaliased)
+        } else {
+            ImageRef::try_parse(&unaliased)
+        };
+        let mut image_ref = match parsed {
+            Ok(image_ref) => image_ref,
+            Err(e) => {
+                finding.reason = Reason::InvalidReference;
+                if self.settings.reject_invalid_images {
+                    return Err(Box::new(finding));
+                }
+                warn!(LOG_DRAIN, "invalid image reference, skipping";
+                    "name" => name, "image" => image, "error" => e.to_string());
+                self.findings.push(finding);
+                return Ok(None);
+            }
+        };
+        if self.is_excluded(&image_ref) {
+            info!(LOG_DRAIN, "image excluded from rewriting, skipping";
+                "name" => name, "image" => image);
+            return Ok(None);
+        }
+        if self.settings.skip_digest_references && image_ref.hash.is_some() {
+            info!(LOG_DRAIN, "image referenced by digest, skipping";
+                "name" => name, "image" => image);
+            return Ok(None);
+        }
+        if self
+            .mirrored
+            .iter()
+            .any(|prefix| image_ref.match_source(prefix).is_some())
+        {
+            // e.g. a pod created from an already mutated template
+            info!(LOG_DRAIN, "image already pulled from a mirror, skipping";
+                "name" => name, "image" => image);
+            return Ok(None);
+        }
+        if let Some(tag) = &self.settings.default_tag {
+            if !image::has_explicit_tag(image) {
+                image_ref.tag = Some(tag.clone());
+            }
+        }
+        let rewrite = self
+            .rewrite_image(&image_ref)
+            .or_else(|| self.default_registry_rewrite(image, &image_ref));
+        let Some(rewrite) = rewrite else {
+            let registry = image_ref.registry().unwrap_or_default();
+            if self.settings.reject_on_no_match && !self.passthrough.contains(&registry) {
+                finding.reason = Reason::NoMatchingRule;
+                return Err(Box::new(finding));
+            }
+            return Ok(None);
+        };
+        self.apply_rewrite(name, image, rewrite)
+    }
+
+    /// Checks the `rewrite` of the `image` of the container or volume `name`
+    /// like [`Mutation::mutate_image`] does, failing over, looking it up at
+    /// its mirror and verifying its signatures, and returns the image to use
+    /// instead, if any.
+    fn apply_rewrite(
+        &mut self,
+        name: &str,
+        image: &str,
+        rewrite: Rewrite,
+    ) -> Result<Option<String>, Box<Finding>> {
+        let mut finding = Finding {
+            rule: None,
+            container: name.to_string(),
+            original: image.to_string(),
+            rewritten: None,
+            reason: Reason::Rewritten,
+            attribution: Attribution::default(),
+        };
+        let mut rewritten = rewrite.image;
+        finding.rule = Some(rewrite.rule);
+        finding.rewritten = Some(rewritten.clone());
+        finding.attribution = rewrite.attribution;
+        if !rewrite.failover.is_empty() {
+            match self.failover(name, &rewritten, &rewrite.failover, &mut finding)? {
+                Some(available) => {
+                    rewritten = available;
+                    finding.rewritten = Some(rewritten.clone());
+                }
+                None => {
+                    self.findings.push(finding);
+                    return Ok(None);
+                }
+            }
+        } else if self.settings.request_replication
+            || self.settings.pin_digest
+            || self.settings.verify_destination_exists
+        {
+            match lookup_image(&rewritten) {
+                Ok(digest) => {
+                    if self.settings.pin_digest {
+                        rewritten = pin_digest(&rewritten, &digest);
+                        finding.rewritten = Some(rewritten.clone());
+                    }
+                }
+                Err(HostError::NotFound(_))
+                    if self.settings.request_replication
+                        || self.settings.verify_destination_exists =>
+                {
+                    finding.reason = Reason::MirrorMissing;
+                    if self.settings.verify_destination_exists
+                        && self.settings.on_missing == MissingPolicy::Reject
+                    {
+                        return Err(Box::new(finding));
+                    }
+                    info!(LOG_DRAIN, "image missing at the mirror, keeping the original image";
+                        "name" => name,
+                        "image" => &rewritten,
+                        "replication" => self.settings.request_replication);
+                    self.findings.push(finding);
+                    if self.settings.request_replication {
+                        self.requested.push(rewritten);
+                    }
+                    return Ok(None);
+                }
+                Err(e) => {
+                    self.on_host_error(name, &rewritten, e, &mut finding)?;
+                }
+            }
+        }
+        self.verify_signatures(name, &rewritten, &mut finding)?;
+        if !image::has_registry(&rewritten) {
+            self.stripped.push(rewritten.clone());
+        }
+        self.findings.push(finding);
+        Ok(Some(rewritten))
+    }
+
+    /// Returns the first of the `rewritten` image and its `failover` images
+    /// found at its mirror, with the manifest host capability, pinned to its
+    /// digest with `pinDigest`. When none of them is found, the original
+    /// image is kept, or the request rejected according to `onMissing`, and
+    /// the `rewritten` image is requested with `requestReplication`.
+    fn failover(
+        &mut self,
+        name: &str,
+        rewritten: &str,
+        failover: &[String],
+        finding: &mut Finding,
+    ) -> Result<Option<String>, Box<Finding>> {
+        for candidate in std::iter::once(rewritten).chain(failover.iter().map(String::as_str)) {
+            match lookup_image(candidate) {
+                Ok(digest) if self.settings.pin_digest => {
+                    return Ok(Some(pin_digest(candidate, &digest)))
+                }
+                Ok(_) => return Ok(Some(candidate.to_string())),
+                Err(HostError::NotFound(_)) => {
+                    info!(LOG_DRAIN, "image missing at the mirror, failing over";
+                        "name" => name, "image" => candidate);
+                }
+                Err(e) => {
+                    self.on_host_error(name, candidate, e, finding)?;
+                    return Ok(Some(candidate.to_string()));
+                }
+            }
+        }
+        finding.reason = Reason::MirrorMissing;
+        if self.settings.on_missing == MissingPolicy::Reject {
+            return Err(Box::new(finding.clone()));
+        }
+        info!(LOG_DRAIN, "image missing at every mirror, keeping the original image";
+            "name" => name, "image" => &finding.original);
+        if self.settings.request_replication {
+            self.requested.push(rewritten.to_string());
+        }
+        Ok(None)
+    }
+
+    /// Verifies the signatures required of the image rewritten for the
+    /// container or volume `name`, rejecting the request when any of them is
+    /// not verified. Failures of the sigstore host capability are handled
+    /// according to `onSignatureHostError`.
+    fn verify_signatures(
+        &self,
+        name: &str,
+        rewritten: &str,
+        finding: &mut Finding,
+    ) -> Result<(), Box<Finding>> {
+        let image_ref = ImageRef::parse(rewritten);
+        for signature in &self.settings.signatures {
+            if image_ref.match_source(signature.image()).is_none() {
+                continue;
+            }
+            match lookup_signature(rewritten, signature) {
+                Ok(true) => {}
+                Ok(false) => {
+                    warn!(LOG_DRAIN, "image not trusted";
+                        "name" => name, "image" => rewritten, "signatures" => signature.image());
+                    finding.reason = Reason::UnverifiedSignature;
+                    return Err(Box::new(finding.clone()));
+                }
+                Err(e) => {
+                    warn!(LOG_DRAIN, "signature verification failed";
+                        "name" => name, "image" => rewritten, "error" => e.to_string());
+                    if self.settings.on_signature_host_error == HostErrorPolicy::FailClosed {
+                        finding.reason = Reason::UnverifiedSignature;
+                        return Err(Box::new(finding.clone()));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles the failure of a host capability call made to check the image
+    /// rewritten for the container or volume `name`: the check is skipped when
+    /// failing open, and the request rejected when failing closed.
+    fn on_host_error(
+        &self,
+        name: &str,
+        rewritten: &str,
+        error: HostError,
+        finding: &mut Finding,
+    ) -> Result<(), Box<Finding>> {
+        warn!(LOG_DRAIN, "host capability error";
+            "name" => name, "image" => rewritten, "error" => error.to_string());
+        match self.settings.on_host_error {
+            HostErrorPolicy::FailOpen => Ok(()),
+            HostErrorPolicy::FailClosed => {
+                finding.reason = Reason::HostCapabilityError;
+                Err(Box::new(finding.clone()))
+            }
+        }
+    }
+
+    /// Returns the short-form `image`, naming no registry, rewritten to the
+    /// `defaultRegistry`, if configured
+    fn default_registry_rewrite(&self, image: &str, image_ref: &ImageRef) -> Option<Rewrite> {
+        let registry = self.settings.default_registry.as_ref()?;
+        if image::has_registry(image) {
+            return None;
+        }
+        Some(Rewrite {
+            rule: "defaultRegistry".to_string(),
+            image: image_ref.rebase(1, registry),
+            failover: Vec::new(),
+            attribution: Attribution::default(),
+        })
+    }
+
+    /// Returns the image rewritten by the matching rule, according to the
+    /// `matchStrategy`, or by every matching rule with the `all` strategy.
+    /// Exact rules take precedence over the other ones, and are applied
+    /// alone.
+    fn rewrite_image(&self, image_ref: &ImageRef) -> Option<Rewrite> {
+        let settings = self.settings;
+        let mut image = image_ref.to_string();
+        if settings.unify_k8s_registries {
+            image = image::unify_k8s_registry(&image);
+        }
+        if let Some(entry) = self.matcher.find_exact(&image, image_ref, &self.os) {
+            return Some(Rewrite {
+                rule: entry.rule.clone(),
+                image: entry.rewrite(&image),
+                failover: Vec::new(),
+                attribution: entry.destination.attribution(),
+            });
+        }
+
+        if settings.match_strategy == MatchStrategy::All {
+            let (applied, rewritten) = self.matcher.rewrite_all(&image, &self.os);
+            let first = applied.first()?;
+            let rules: Vec<&str> = applied.iter().map(|entry| entry.rule.as_str()).collect();
+            return Some(Rewrite {
+                rule: rules.join("+"),
+                image: rewritten,
+                failover: Vec::new(),
+                attribution: first.destination.attribution(),
+            });
+        }
+        let entry = self.matcher.find(&image, image_ref, &self.os)?;
+        Some(Rewrite {
+            rule: entry.rule.clone(),
+            image: entry.rewrite(&image),
+            failover: entry.failover(&image),
+            attribution: entry.destination.attribution(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use k8s_openapi::api::apps::v1 as apiapps;
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+
+    /// Returns a pod named `app` running the `containers`
+    fn pod(containers: Value) -> apicore::Pod {
+        serde_json::from_value(json!({
+            "metadata": {"name": "app"},
+            "spec": {"containers": containers}
+        }))
+        .unwrap()
+    }
+
+    /// Returns the images of the containers of `pod`
+    fn images(pod: &apicore::Pod) -> Vec<&str> {
+        pod.spec
+            .iter()
+            .flat_map(|spec| &spec.containers)
+            .filter_map(|container| container.image.as_deref())
+            .collect()
+    }
+
+    #[test]
+    fn mutate_pod_strips_server_owned_fields() -> Result<(), ()> {
+        let pod: apicore::Pod = serde_json::from_value(json!({
+            "metadata": {
+                "name": "nginx",
+                "managedFields": [{"manager": "kubectl", "operation": "Update"}]
+            },
+            "spec": {
+                "containers": [{"name": "nginx", "image": "nginx"}]
+            },
+            "status": {"phase": "Pending"}
+        }))
+        .unwrap();
+
+        let pod = mutate_workload(pod, &Settings::default()).unwrap().0;
+        assert!(pod.status.is_none());
+        assert!(pod.metadata.managed_fields.is_none());
+        assert_eq!(pod.metadata.name.as_deref(), Some("nginx"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn unify_k8s_registries() -> Result<(), ()> {
+        let pod = pod(json!([
+            {"name": "pause", "image": "registry.k8s.io/pause:3.9"},
+            {"name": "etcd", "image": "k8s.gcr.io/etcd:3.5.6-0"},
+            {"name": "coredns", "image": "eu.k8s.gcr.io/coredns/coredns:v1.9.3"}
+        ]));
+        let mut settings = Settings {
+            repos: HashMap::from([("k8s.gcr.io".to_string(), "k8s.tencentcloudcr.com".into())]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            images(&mutate_workload(pod.clone(), &settings).unwrap().0),
+            vec![
+                "registry.k8s.io/pause:3.9",
+                "k8s.tencentcloudcr.com/etcd:3.5.6-0",
+                "eu.k8s.gcr.io/coredns/coredns:v1.9.3",
+            ]
+        );
+
+        settings.unify_k8s_registries = true;
+        assert_eq!(
+            images(&mutate_workload(pod, &settings).unwrap().0),
+            vec![
+                "k8s.tencentcloudcr.com/pause:3.9",
+                "k8s.tencentcloudcr.com/etcd:3.5.6-0",
+                "k8s.tencentcloudcr.com/coredns/coredns:v1.9.3",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn org_rules_match_any_registry() -> Result<(), ()> {
+        let settings = Settings {
+            repos: HashMap::from([(
+                "docker.io".to_string(),
+                "dockerhub.tencentcloudcr.com".into(),
+            )]),
+            orgs: HashMap::from([("bitnami".to_string(), "registry.corp/bitnami".into())]),
+            ..Default::default()
+        };
+        let mutation = Mutation::new(&settings, DEFAULT_OS.to_string());
+        let rewrite = |image: &str| {
+            mutation
+                .rewrite_image(&ImageRef::parse(image))
+                .map(|rewrite| rewrite.image)
+        };
+
+        assert_eq!(
+            rewrite("bitnami/nginx").as_deref(),
+            Some("registry.corp/bitnami/nginx:latest")
+        );
+        assert_eq!(
+            rewrite("quay.io/bitnami/charts/redis@sha256:abc").as_deref(),
+            Some("registry.corp/bitnami/charts/redis@sha256:abc")
+        );
+        assert_eq!(
+            rewrite("alpine:3.17").as_deref(),
+            Some("dockerhub.tencentcloudcr.com/library/alpine:3.17")
+        );
+        assert_eq!(rewrite("gcr.io/bitnami:1.0"), None);
+
+        let settings = Settings {
+            orgs: HashMap::from([("bitnami".to_string(), "registry.corp/bitnami/".into())]),
+            ..Default::default()
+        };
+        let mutation = Mutation::new(&settings, DEFAULT_OS.to_string());
+        assert_eq!(
+            mutation
+                .rewrite_image(&ImageRef::parse("bitnami/nginx:1"))
+                .map(|rewrite| rewrite.image)
+                .as_deref(),
+            Some("registry.corp/bitnami/nginx:1")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn stripped_images_are_final() -> Result<(), ()> {
+        let deployment: apiapps::Deployment = serde_json::from_value(json!({
+            "metadata": {"name": "app"},
+            "spec": {
+                "selector": {"matchLabels": {"app": "app"}},
+                "template": {
+                    "metadata": {"labels": {"app": "app"}},
+                    "spec": {"containers": [
+                        {"name": "app", "image": "gcr.io/foo/bar:v1"},
+                        {"name": "sidecar", "image": "busybox:1.36"}
+                    ]}
+                }
+            }
+        }))
+        .unwrap();
+        let settings: Settings = serde_json::from_value(json!({
+            "repos": {
+                "gcr.io": {"action": "stripRegistry"},
+                "docker.io": "mirror.corp/docker"
+            }
+        }))
+        .unwrap();
+
+        let (deployment, _) = mutate_workload(deployment, &settings).unwrap();
+        let template = deployment.spec.unwrap().template;
+        let metadata = template.metadata.unwrap();
+        assert_eq!(
+            metadata.annotations.as_ref().unwrap()
+                ["mutate-repos-policy.kubewarden.io/stripped-images"],
+            "foo/bar:v1"
+        );
+
+        // the pod later created from the template by its ReplicaSet
+        let pod = apicore::Pod {
+            metadata,
+            spec: template.spec,
+            status: None,
+        };
+        let (pod, findings) = mutate_workload(pod, &settings).unwrap();
+        let images: Vec<String> = pod
+            .spec
+            .unwrap()
+            .containers
+            .into_iter()
+            .map(|c| c.image.unwrap())
+            .collect();
+        assert_eq!(
+            images,
+            vec!["foo/bar:v1", "mirror.corp/docker/library/busybox:1.36"]
+        );
+        assert!(findings.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn org_rules_are_ordered_like_sources() -> Result<(), ()> {
+        let settings: Settings = serde_json::from_value(json!({
+            "repos": {
+                "docker.io": "dockerhub.example.com",
+                "quay.io/bitnami/nginx": "quay.example.com/nginx"
+            },
+            "orgs": {
+                "bitnami": {
+                    "destination": "registry.corp/bitnami",
+                    "os": "linux",
+                    "team": "platform",
+                    "tagSuffix": "-corp"
+                }
+            }
+        }))
+        .unwrap();
+        let rewrite = |image: &str, os: &str| {
+            Mutation::new(&settings, os.to_string()).rewrite_image(&ImageRef::parse(image))
+        };
+
+        let rewritten = rewrite("bitnami/redis:7.2", "linux").unwrap();
+        assert_eq!(rewritten.image, "registry.corp/bitnami/redis:7.2-corp");
+        assert_eq!(rewritten.rule, "orgs/bitnami");
+        assert_eq!(rewritten.attribution.team.as_deref(), Some("platform"));
+        // a more specific source wins over the organization
+        assert_eq!(
+            rewrite("quay.io/bitnami/nginx:1.25", "linux")
+                .map(|rewrite| rewrite.image)
+                .as_deref(),
+            Some("quay.example.com/nginx:1.25")
+        );
+        // the conditions of the organization rule are honored
+        assert_eq!(
+            rewrite("bitnami/redis:7.2", "windows")
+                .map(|rewrite| rewrite.image)
+                .as_deref(),
+            Some("dockerhub.example.com/bitnami/redis:7.2")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn mutate_image_volumes() -> Result<(), ()> {
+        let pod: apicore::Pod = serde_json::from_value(json!({
+            "metadata": {"name": "model-server"},
+            "spec": {
+                "containers": [{"name": "server", "image": "quay.io/foo/server:v1"}],
+                "volumes": [
+                    {"name": "model", "image": {"reference": "quay.io/foo/model:v1", "pullPolicy": "IfNotPresent"}},
+                    {"name": "scratch", "emptyDir": {}}
+                ]
+            }
+        }))
+        .unwrap();
+        let settings = Settings {
+            repos: HashMap::from([("quay.io".to_string(), "quay.tencentcloudcr.com".into())]),
+            ..Default::default()
+        };
+
+        let (pod, _) = mutate_workload(pod, &settings).unwrap();
+        let volumes = pod.spec.unwrap().volumes.unwrap();
+        let model = volumes[0].image.as_ref().unwrap();
+        assert_eq!(
+            model.reference.as_deref(),
+            Some("quay.tencentcloudcr.com/foo/model:v1")
+        );
+        assert_eq!(model.pull_policy.as_deref(), Some("IfNotPresent"));
+        assert!(volumes[1].image.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn os_conditions() -> Result<(), ()> {
+        let image = |os: serde_json::Value, settings: &Settings| -> String {
+            let mut spec = json!({
+                "containers": [{"name": "app", "image": "mcr.microsoft.com/windows/nanoserver:ltsc2022"}]
+            });
+            spec.as_object_mut()
+                .unwrap()
+                .extend(os.as_object().unwrap().clone());
+            let pod: apicore::Pod =
+                serde_json::from_value(json!({"metadata": {"name": "app"}, "spec": spec})).unwrap();
+            let (pod, _) = mutate_workload(pod, settings).unwrap();
+            images(&pod)[0].to_string()
+        };
+
+        let mut settings: Settings = serde_json::from_value(json!({
+            "repos": {
+                "mcr.microsoft.com": {"destination": "windows.example.com", "os": "windows"}
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(
+            image(json!({}), &settings),
+            "mcr.microsoft.com/windows/nanoserver:ltsc2022"
+        );
+        assert_eq!(
+            image(json!({"os": {"name": "windows"}}), &settings),
+            "windows.example.com/windows/nanoserver:ltsc2022"
+        );
+        let windows_selector = json!({"nodeSelector": {"kubernetes.io/os": "windows"}});
+        assert_eq!(
+            image(windows_selector.clone(), &settings),
+            "windows.example.com/windows/nanoserver:ltsc2022"
+        );
+
+        settings.excluded_os = vec!["windows".to_string()];
+        assert_eq!(
+            image(windows_selector, &settings),
+            "mcr.microsoft.com/windows/nanoserver:ltsc2022"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn debug_images() -> Result<(), ()> {
+        let pod: apicore::Pod = serde_json::from_value(json!({
+            "metadata": {"name": "nginx"},
+            "spec": {
+                "containers": [{"name": "nginx", "image": "nginx"}],
+                "ephemeralContainers": [
+                    {"name": "debugger-1", "image": "busybox:1.28"},
+                    {"name": "debugger-2", "image": "docker.io/nicolaka/netshoot"},
+                    {"name": "debugger-3", "image": "alpine"}
+                ]
+            }
+        }))
+        .unwrap();
+        let settings = Settings {
+            debug_images: BTreeMap::from([
+                (
+                    "busybox".to_string(),
+                    "registry.corp/tools/busybox:1.36".to_string(),
+                ),
+                // same repository, the first in order wins
+                (
+                    "docker.io/library/busybox".to_string(),
+                    "registry.corp/other/busybox:1.36".to_string(),
+                ),
+                (
+                    "nicolaka/netshoot".to_string(),
+                    "registry.corp/tools/netshoot:v0.11".to_string(),
+                ),
+            ]),
+            ..Default::default()
+        };
+
+        let (pod, findings) = mutate_workload(pod, &settings).unwrap();
+        let spec = pod.spec.unwrap();
+        let images: Vec<String> = spec
+            .ephemeral_containers
+            .unwrap()
+            .into_iter()
+            .map(|c| c.image.unwrap())
+            .collect();
+        assert_eq!(
+            images,
+            vec![
+                "registry.corp/tools/busybox:1.36",
+                "registry.corp/tools/netshoot:v0.11",
+                "alpine",
+            ]
+        );
+        assert_eq!(spec.containers[0].image.as_deref(), Some("nginx"));
+        assert_eq!(findings[0].rule.as_deref(), Some("debugImages/busybox"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn debug_images_are_checked() -> Result<(), ()> {
+        let pod = |image: &str| -> apicore::Pod {
+            serde_json::from_value(json!({
+                "metadata": {"name": "nginx"},
+                "spec": {
+                    "containers": [{"name": "nginx", "image": "nginx"}],
+                    "ephemeralContainers": [{"name": "debugger", "image": image}]
+                }
+            }))
+            .unwrap()
+        };
+        let settings: Settings = serde_json::from_value(json!({
+            "debugImages": {
+                "busybox": "registry.corp/tools/busybox:1.36",
+                "nicolaka/netshoot": "registry.corp/tools/netshoot:v0.11"
+            },
+            "excludeImages": ["docker.io/nicolaka/*"],
+            "signatures": [{"image": "registry.corp/tools/*", "pubKeys": ["KEY"]}]
+        }))
+        .unwrap();
+        host::mock(host::MockHost {
+            signed: vec!["registry.corp/tools/busybox:1.36".to_string()],
+            ..Default::default()
+        });
+        let image = |pod: apicore::Pod| {
+            pod.spec.unwrap().ephemeral_containers.unwrap()[0]
+                .image
+                .clone()
+                .unwrap()
+        };
+
+        let (mutated, _) = mutate_workload(pod("busybox:1.28"), &settings).unwrap();
+        assert_eq!(image(mutated), "registry.corp/tools/busybox:1.36");
+        // excluded images are left untouched
+        let (mutated, findings) = mutate_workload(pod("nicolaka/netshoot"), &settings).unwrap();
+        assert_eq!(image(mutated), "nicolaka/netshoot");
+        assert!(findings.is_empty());
+
+        // the signatures of the approved images are verified too
+        host::mock(host::MockHost::default());
+        let finding = mutate_workload(pod("busybox:1.28"), &settings).unwrap_err();
+        assert_eq!(finding.reason, Reason::UnverifiedSignature);
+        assert_eq!(finding.rule.as_deref(), Some("debugImages/busybox"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn ephemeral_containers_subresource() -> Result<(), ()> {
+        let pod: apicore::Pod = serde_json::from_value(json!({
+            "metadata": {"name": "nginx"},
+            "spec": {
+                "containers": [{"name": "nginx", "image": "nginx"}],
+                "ephemeralContainers": [
+                    {"name": "debugger-1", "image": "busybox:1.28"},
+                    {"name": "debugger-2", "image": "busybox:1.28"},
+                    {"name": "debugger-3", "image": "nicolaka/netshoot"}
+                ]
+            }
+        }))
+        .unwrap();
+        let settings = Settings {
+            repos: HashMap::from([("docker.io".to_string(), "mirror.corp".into())]),
+            debug_images: BTreeMap::from([(
+                "nicolaka/netshoot".to_string(),
+                "registry.corp/tools/netshoot:v0.11".to_string(),
+            )]),
+            ..Default::default()
+        };
+
+        let (pod, findings) =
+            mutate_ephemeral_containers(pod, &["debugger-1".to_string()], &settings).unwrap();
+        let spec = pod.spec.unwrap();
+        let images: Vec<String> = spec
+            .ephemeral_containers
+            .unwrap()
+            .into_iter()
+            .map(|c| c.image.unwrap())
+            .collect();
+        assert_eq!(
+            images,
+            vec![
+                "busybox:1.28",
+                "mirror.corp/library/busybox:1.28",
+                "registry.corp/tools/netshoot:v0.11",
+            ]
+        );
+        // regular containers cannot be changed through the subresource
+        assert_eq!(spec.containers[0].image.as_deref(), Some("nginx"));
+        assert_eq!(findings.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn host_errors() -> Result<(), ()> {
+        host::mock(host::MockHost {
+            unavailable: Some("connection refused".to_string()),
+            ..Default::default()
+        });
+        let pod = pod(json!([{"name": "nginx", "image": "nginx"}]));
+        let mut settings = Settings {
+            repos: HashMap::from([("docker.io".to_string(), "dockerhub.example.com".into())]),
+            request_replication: true,
+            ..Default::default()
+        };
+
+        let (mutated, _) = mutate_workload(pod.clone(), &settings).unwrap();
+        assert_eq!(
+            images(&mutated)[0],
+            "dockerhub.example.com/library/nginx:latest"
+        );
+        assert!(mutated.metadata.annotations.is_none());
+
+        settings.on_host_error = HostErrorPolicy::FailClosed;
+        let finding = mutate_workload(pod, &settings).unwrap_err();
+        assert_eq!(finding.reason, Reason::HostCapabilityError);
+
+        Ok(())
+    }
+
+    #[test]
+    fn default_tag() -> Result<(), ()> {
+        let pod = pod(json!([
+            {"name": "nginx", "image": "nginx"},
+            {"name": "latest", "image": "nginx:latest"},
+            {"name": "digest", "image": "nginx@sha256:abc"},
+            {"name": "unmatched", "image": "quay.io/foo/bar"}
+        ]));
+        let settings = Settings {
+            repos: HashMap::from([("docker.io".to_string(), "dockerhub.example.com".into())]),
+            default_tag: Some("stable".to_string()),
+            ..Default::default()
+        };
+
+        let (pod, _) = mutate_workload(pod, &settings).unwrap();
+        let images: Vec<String> = pod
+            .spec
+            .unwrap()
+            .containers
+            .into_iter()
+            .map(|c| c.image.unwrap())
+            .collect();
+        assert_eq!(
+            images,
+            vec![
+                "dockerhub.example.com/library/nginx:stable",
+                "dockerhub.example.com/library/nginx:latest",
+                "dockerhub.example.com/library/nginx@sha256:abc",
+                "quay.io/foo/bar",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn record_original_image() -> Result<(), ()> {
+        let pod: apicore::Pod = serde_json::from_value(json!({
+            "metadata": {"name": "nginx"},
+            "spec": {
+                "containers": [
+                    {"name": "nginx", "image": "nginx:1.25"},
+                    {"name": "bar", "image": "quay.io/foo/bar"}
+                ],
+                "initContainers": [{"name": "init", "image": "docker.io/library/alpine:3.17"}],
+                // named like a container, recorded separately
+                "volumes": [{"name": "nginx", "image": {"reference": "foo/model:v1"}}]
+            }
+        }))
+        .unwrap();
+        let mut settings = Settings {
+            repos: HashMap::from([("docker.io".to_string(), "dockerhub.example.com".into())]),
+            ..Default::default()
+        };
+
+        let (mutated, _) = mutate_workload(pod.clone(), &settings).unwrap();
+        assert!(mutated.metadata.annotations.is_none());
+
+        settings.record_original_image = true;
+        let (mutated, _) = mutate_workload(pod, &settings).unwrap();
+        assert_eq!(
+            mutated.metadata.annotations.unwrap()
+                ["mutate-repos-policy.kubewarden.io/original-images"],
+            r#"{"containers/nginx":"nginx:1.25","initContainers/init":"docker.io/library/alpine:3.17","volumes/nginx":"foo/model:v1"}"#
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn record_mutation_summary() -> Result<(), ()> {
+        let pod = pod(json!([
+            {"name": "nginx", "image": "nginx:1.25"},
+            {"name": "bar", "image": "quay.io/foo/bar"}
+        ]));
+        let mut settings = Settings {
+            repos: HashMap::from([("docker.io".to_string(), "dockerhub.example.com".into())]),
+            ..Default::default()
+        };
+
+        let (mutated, _) = mutate_workload(pod.clone(), &settings).unwrap();
+        assert!(mutated.metadata.annotations.is_none());
+
+        settings.record_mutation_summary = true;
+        let (mutated, _) = mutate_workload(pod, &settings).unwrap();
+        assert_eq!(
+            mutated.metadata.annotations.unwrap()
+                ["mutate-repos-policy.kubewarden.io/mutation-summary"],
+            "nginx: nginx:1.25 -> dockerhub.example.com/library/nginx:1.25 (rewritten, rule repos/docker.io)"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_on_no_match() -> Result<(), ()> {
+        let pod = |image: &str| pod(json!([{"name": "app", "image": image}]));
+        let mut settings = Settings {
+            repos: HashMap::from([("docker.io".to_string(), "dockerhub.example.com".into())]),
+            ..Default::default()
+        };
+        assert!(mutate_workload(pod("quay.io/foo/bar"), &settings).is_ok());
+
+        settings.reject_on_no_match = true;
+        let finding = mutate_workload(pod("quay.io/foo/bar"), &settings).unwrap_err();
+        assert_eq!(
+            finding.to_string(),
+            "app: quay.io/foo/bar (no matching rule for this registry)"
+        );
+        // images already rewritten are admitted again
+        assert!(mutate_workload(pod("dockerhub.example.com/library/nginx"), &settings).is_ok());
+
+        settings.passthrough_registries = vec!["quay.io".to_string()];
+        assert!(mutate_workload(pod("quay.io/foo/bar"), &settings).is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn skip_digest_references() -> Result<(), ()> {
+        let pod = pod(json!([
+            {"name": "pinned", "image": "quay.io/foo/bar@sha256:1234"},
+            {"name": "tagged", "image": "quay.io/foo/bar:v1"}
+        ]));
+        let mut settings = Settings {
+            repos: HashMap::from([("quay.io".to_string(), "mirror.corp/quay".into())]),
+            skip_digest_references: true,
+            ..Default::default()
+        };
+
+        let (mutated, findings) = mutate_workload(pod.clone(), &settings).unwrap();
+        assert_eq!(
+            images(&mutated),
+            vec!["quay.io/foo/bar@sha256:1234", "mirror.corp/quay/foo/bar:v1"]
+        );
+        assert_eq!(findings.len(), 1);
+
+        settings.skip_digest_references = false;
+        let (mutated, _) = mutate_workload(pod, &settings).unwrap();
+        assert_eq!(images(&mutated)[0], "mirror.corp/quay/foo/bar@sha256:1234");
+
+        Ok(())
+    }
+
+    #[test]
+    fn mirrored_images_are_left_untouched() -> Result<(), ()> {
+        let pod = pod(json!([
+            {"name": "mirrored", "image": "registry.corp/bitnami/nginx:1.25"},
+            {"name": "upstream", "image": "bitnami/nginx:1.25"}
+        ]));
+        let settings = Settings {
+            orgs: HashMap::from([("bitnami".to_string(), "registry.corp/bitnami".into())]),
+            ..Default::default()
+        };
+
+        let (pod, findings) = mutate_workload(pod, &settings).unwrap();
+        assert_eq!(
+            images(&pod),
+            vec![
+                "registry.corp/bitnami/nginx:1.25",
+                "registry.corp/bitnami/nginx:1.25"
+            ]
+        );
+        assert_eq!(findings.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_images() -> Result<(), ()> {
+        let invalid = pod(json!([
+            {"name": "invalid", "image": "quay.io/Foo/bar@@sha256:1234"},
+            {"name": "valid", "image": "quay.io/foo/bar:v1"}
+        ]));
+        let mut settings = Settings {
+            repos: HashMap::from([("quay.io".to_string(), "mirror.corp/quay".into())]),
+            ..Default::default()
+        };
+
+        let (mutated, findings) = mutate_workload(invalid.clone(), &settings).unwrap();
+        assert_eq!(
+            images(&mutated),
+            vec![
+                "quay.io/Foo/bar@@sha256:1234",
+                "mirror.corp/quay/foo/bar:v1"
+            ]
+        );
+        assert_eq!(findings[0].reason, Reason::InvalidReference);
+
+        settings.reject_invalid_images = true;
+        let finding = mutate_workload(invalid, &settings).unwrap_err();
+        assert_eq!(finding.container, "invalid");
+        assert_eq!(finding.reason, Reason::InvalidReference);
+
+        // digests of the registered algorithms must be canonical
+        let short = pod(json!([
+            {"name": "short-digest", "image": "quay.io/foo/bar@sha256:1234"}
+        ]));
+        settings.reject_invalid_images = false;
+        let (_, findings) = mutate_workload(short.clone(), &settings).unwrap();
+        assert_eq!(findings[0].reason, Reason::Rewritten);
+
+        settings.strict_image_references = true;
+        let (_, findings) = mutate_workload(short.clone(), &settings).unwrap();
+        assert_eq!(findings[0].reason, Reason::InvalidReference);
+
+        settings.reject_invalid_images = true;
+        let finding = mutate_workload(short, &settings).unwrap_err();
+        assert_eq!(finding.container, "short-digest");
+
+        Ok(())
+    }
+
+    #[test]
+    fn exact_rules() -> Result<(), ()> {
+        let pod = pod(json!([
+            {"name": "nginx", "image": "bitnami/nginx:1.25"},
+            {"name": "redis", "image": "bitnami/redis:7.2"}
+        ]));
+        let settings: Settings = serde_json::from_value(json!({
+            "orgs": {"bitnami": "registry.corp/bitnami"},
+            "rules": [
+                {"exact": "docker.io/bitnami/nginx:1.25", "replace": "registry.corp/nginx:1.25-fips"}
+            ]
+        }))
+        .unwrap();
+
+        let (pod, findings) = mutate_workload(pod, &settings).unwrap();
+        let images: Vec<String> = pod
+            .spec
+            .unwrap()
+            .containers
+            .into_iter()
+            .map(|c| c.image.unwrap())
+            .collect();
+        assert_eq!(
+            images,
+            vec![
+                "registry.corp/nginx:1.25-fips",
+                "registry.corp/bitnami/redis:7.2"
+            ]
+        );
+        assert_eq!(
+            findings[0].rule.as_deref(),
+            Some("rules/docker.io/bitnami/nginx:1.25")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn registry_aliases() -> Result<(), ()> {
+        let pod = pod(json!([
+            {"name": "index", "image": "index.docker.io/library/nginx:1.25"},
+            {"name": "registry", "image": "registry-1.docker.io/bitnami/redis:7"},
+            {"name": "custom", "image": "docker.corp.example/alpine:3.19"}
+        ]));
+        let settings = Settings {
+            repos: HashMap::from([("docker.io".to_string(), "mirror.corp/docker".into())]),
+            registry_aliases: HashMap::from([(
+                "docker.corp.example".to_string(),
+                "docker.io".to_string(),
+            )]),
+            ..Default::default()
+        };
+
+        let (pod, findings) = mutate_workload(pod, &settings).unwrap();
+        let images: Vec<String> = pod
+            .spec
+            .unwrap()
+            .containers
+            .into_iter()
+            .map(|c| c.image.unwrap())
+            .collect();
+        assert_eq!(
+            images,
+            vec![
+                "mirror.corp/docker/library/nginx:1.25",
+                "mirror.corp/docker/bitnami/redis:7",
+                "mirror.corp/docker/library/alpine:3.19",
+            ]
+        );
+        assert_eq!(findings[0].original, "index.docker.io/library/nginx:1.25");
+
+        Ok(())
+    }
+
+    #[test]
+    fn container_filters() -> Result<(), ()> {
+        let pod: apicore::Pod = serde_json::from_value(json!({
+            "metadata": {"name": "app"},
+            "spec": {
+                "containers": [
+                    {"name": "app", "image": "quay.io/foo/app:v1"},
+                    {"name": "istio-proxy", "image": "quay.io/istio/proxyv2:1.20"}
+                ],
+                "initContainers": [
+                    {"name": "istio-proxy", "image": "quay.io/istio/proxyv2:1.20"}
+                ]
+            }
+        }))
+        .unwrap();
+        let settings: Settings = serde_json::from_value(json!({
+            "repos": {"quay.io": "mirror.corp/quay"},
+            "containerFilters": {"containers": {"exclude": ["istio-*"]}}
+        }))
+        .unwrap();
+
+        let (pod, findings) = mutate_workload(pod, &settings).unwrap();
+        assert_eq!(
+            images(&pod),
+            vec!["mirror.corp/quay/foo/app:v1", "quay.io/istio/proxyv2:1.20"]
+        );
+        let spec = pod.spec.unwrap();
+        // the filter of the containers does not apply to init containers
+        assert_eq!(
+            spec.init_containers.unwrap()[0].image.as_deref(),
+            Some("mirror.corp/quay/istio/proxyv2:1.20")
+        );
+        assert_eq!(findings.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn env_vars_and_args() -> Result<(), ()> {
+        let pod = pod(json!([{
+            "name": "operator",
+            "image": "quay.io/foo/operator:v1",
+            "env": [
+                {"name": "RELATED_IMAGE_DB", "value": "quay.io/foo/db:v2"},
+                {"name": "RELATED_IMAGE_CACHE", "value": "$(CACHE_IMAGE)"},
+                {"name": "LOG_LEVEL", "value": "quay.io/foo/db:v2"}
+            ],
+            "args": [
+                "--sidecar-image=quay.io/foo/sidecar:v3",
+                "--init-image",
+                "quay.io/foo/init:v4",
+                "--verbose",
+                "quay.io/foo/db:v2"
+            ]
+        }]));
+        let settings: Settings = serde_json::from_value(json!({
+            "repos": {"quay.io": "mirror.corp/quay"},
+            "imageEnvVars": ["RELATED_IMAGE_*"],
+            "imageArgs": ["--sidecar-image", "--init-*"]
+        }))
+        .unwrap();
+
+        let (pod, findings) = mutate_workload(pod, &settings).unwrap();
+        let container = &pod.spec.unwrap().containers[0];
+        let env = container.env.as_ref().unwrap();
+        assert_eq!(env[0].value.as_deref(), Some("mirror.corp/quay/foo/db:v2"));
+        assert_eq!(env[1].value.as_deref(), Some("$(CACHE_IMAGE)"));
+        assert_eq!(env[2].value.as_deref(), Some("quay.io/foo/db:v2"));
+        assert_eq!(
+            container.args.as_deref().unwrap(),
+            [
+                "--sidecar-image=mirror.corp/quay/foo/sidecar:v3",
+                "--init-image",
+                "mirror.corp/quay/foo/init:v4",
+                "--verbose",
+                "quay.io/foo/db:v2"
+            ]
+        );
+        let locations: Vec<&str> = findings.iter().map(|f| f.container.as_str()).collect();
+        assert_eq!(
+            locations,
+            [
+                "operator/env/RELATED_IMAGE_DB",
+                "operator/args[0]",
+                "operator/args[2]",
+                "operator"
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn image_annotations() -> Result<(), ()> {
+        let pod: apicore::Pod = serde_json::from_value(json!({
+            "metadata": {"name": "app", "annotations": {
+                "sidecar.istio.io/proxyImage": "docker.io/istio/proxyv2:1.22.0",
+                "vault.hashicorp.com/agent-image": "hashicorp/vault:1.17",
+                "vault.hashicorp.com/role": "app"
+            }},
+            "spec": {"containers": [{"name": "app", "image": "quay.io/foo/app:v1"}]}
+        }))
+        .unwrap();
+        let settings: Settings = serde_json::from_value(json!({
+            "repos": {"docker.io": "mirror.corp/dockerhub"},
+            "imageAnnotations": ["sidecar.istio.io/*", "vault.hashicorp.com/agent-image"]
+        }))
+        .unwrap();
+
+        let (pod, findings) = mutate_workload(pod, &settings).unwrap();
+        assert_eq!(
+            pod.metadata.annotations.unwrap(),
+            BTreeMap::from([
+                (
+                    "sidecar.istio.io/proxyImage".to_string(),
+                    "mirror.corp/dockerhub/istio/proxyv2:1.22.0".to_string()
+                ),
+                (
+                    "vault.hashicorp.com/agent-image".to_string(),
+                    "mirror.corp/dockerhub/hashicorp/vault:1.17".to_string()
+                ),
+                ("vault.hashicorp.com/role".to_string(), "app".to_string()),
+            ])
+        );
+        let locations: Vec<&str> = findings.iter().map(|f| f.container.as_str()).collect();
+        assert_eq!(
+            locations,
+            [
+                "annotations/sidecar.istio.io/proxyImage",
+                "annotations/vault.hashicorp.com/agent-image"
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn set_image_pull_policy() -> Result<(), ()> {
+        let pod = pod(json!([
+            {"name": "app", "image": "quay.io/foo/app:v1", "imagePullPolicy": "Always"},
+            {"name": "local", "image": "registry.corp/app:v1", "imagePullPolicy": "Always"}
+        ]));
+        let settings: Settings = serde_json::from_value(json!({
+            "repos": {"quay.io": "mirror.corp/quay"},
+            "setImagePullPolicy": "IfNotPresent"
+        }))
+        .unwrap();
+
+        let (pod, _) = mutate_workload(pod, &settings).unwrap();
+        let containers = pod.spec.unwrap().containers;
+        assert_eq!(
+            containers[0].image_pull_policy.as_deref(),
+            Some("IfNotPresent")
+        );
+        assert_eq!(containers[1].image_pull_policy.as_deref(), Some("Always"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn inject_original_image() -> Result<(), ()> {
+        let pod = pod(json!([
+            {"name": "app", "image": "quay.io/foo/app:v1"},
+            {
+                "name": "worker",
+                "image": "quay.io/foo/worker:v1",
+                "env": [{"name": "KW_ORIGINAL_IMAGE", "value": "quay.io/foo/worker:v0"}]
+            },
+            {"name": "local", "image": "registry.corp/app:v1"}
+        ]));
+        let mut settings: Settings = serde_json::from_value(json!({
+            "repos": {"quay.io": "mirror.corp/quay"},
+            "injectOriginalImage": true
+        }))
+        .unwrap();
+
+        let (mutated, _) = mutate_workload(pod.clone(), &settings).unwrap();
+        let containers = mutated.spec.unwrap().containers;
+        let env = |container: &apicore::Container| -> Vec<(String, Option<String>)> {
+            container
+                .env
+                .iter()
+                .flatten()
+                .map(|var| (var.name.clone(), var.value.clone()))
+                .collect()
+        };
+        assert_eq!(
+            env(&containers[0]),
+            [(
+                "KW_ORIGINAL_IMAGE".to_string(),
+                Some("quay.io/foo/app:v1".to_string())
+            )]
+        );
+        // already injected by a previous admission
+        assert_eq!(
+            env(&containers[1]),
+            [(
+                "KW_ORIGINAL_IMAGE".to_string(),
+                Some("quay.io/foo/worker:v0".to_string())
+            )]
+        );
+        assert!(containers[2].env.is_none());
+
+        settings.original_image_env_var = Some("SOURCE_IMAGE".to_string());
+        let (mutated, _) = mutate_workload(pod, &settings).unwrap();
+        let containers = mutated.spec.unwrap().containers;
+        assert_eq!(
+            env(&containers[0]),
+            [(
+                "SOURCE_IMAGE".to_string(),
+                Some("quay.io/foo/app:v1".to_string())
+            )]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn exclude_images() -> Result<(), ()> {
+        let pod = pod(json!([
+            {"name": "pause", "image": "k8s.gcr.io/pause:3.9"},
+            {"name": "pause-old", "image": "registry.k8s.io/pause:3.8"},
+            {"name": "csi", "image": "registry.k8s.io/sig-storage/csi-provisioner:v4"},
+            {"name": "policy-server", "image": "index.docker.io/kubewarden/policy-server:v1"},
+            {"name": "app", "image": "docker.io/foo/app:v1"}
+        ]));
+        let settings: Settings = serde_json::from_value(json!({
+            "repos": {
+                "registry.k8s.io": "mirror.corp/k8s",
+                "docker.io": "mirror.corp/docker"
+            },
+            "unifyK8sRegistries": true,
+            "rejectOnNoMatch": true,
+            "excludeImages": [
+                "registry.k8s.io/pause:3.9",
+                "registry.k8s.io/sig-storage",
+                "docker.io/kubewarden/*"
+            ]
+        }))
+        .unwrap();
+
+        let (pod, findings) = mutate_workload(pod, &settings).unwrap();
+        let images: Vec<String> = pod
+            .spec
+            .unwrap()
+            .containers
+            .into_iter()
+            .map(|ctr| ctr.image.unwrap())
+            .collect();
+        assert_eq!(
+            images,
+            [
+                "k8s.gcr.io/pause:3.9",
+                "mirror.corp/k8s/pause:3.8",
+                "registry.k8s.io/sig-storage/csi-provisioner:v4",
+                "index.docker.io/kubewarden/policy-server:v1",
+                "mirror.corp/docker/foo/app:v1"
+            ]
+        );
+        assert_eq!(findings.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn container_toggles() -> Result<(), ()> {
+        let pod: apicore::Pod = serde_json::from_value(json!({
+            "metadata": {"name": "app"},
+            "spec": {
+                "containers": [{"name": "app", "image": "quay.io/foo/app:v1"}],
+                "initContainers": [{"name": "bootstrap", "image": "quay.io/vendor/init:v1"}],
+                "ephemeralContainers": [{"name": "debugger", "image": "quay.io/foo/debug:v1"}]
+            }
+        }))
+        .unwrap();
+        let settings: Settings = serde_json::from_value(json!({
+            "repos": {"quay.io": "mirror.corp/quay"},
+            "mutateInitContainers": false
+        }))
+        .unwrap();
+
+        let (pod, findings) = mutate_workload(pod, &settings).unwrap();
+        let spec = pod.spec.unwrap();
+        assert_eq!(
+            spec.containers[0].image.as_deref(),
+            Some("mirror.corp/quay/foo/app:v1")
+        );
+        assert_eq!(
+            spec.init_containers.unwrap()[0].image.as_deref(),
+            Some("quay.io/vendor/init:v1")
+        );
+        assert_eq!(
+            spec.ephemeral_containers.unwrap()[0].image.as_deref(),
+            Some("mirror.corp/quay/foo/debug:v1")
+        );
+        assert_eq!(findings.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn default_registry() -> Result<(), ()> {
+        let pod = pod(json!([
+            {"name": "alpine", "image": "alpine:3.19"},
+            {"name": "nginx", "image": "bitnami/nginx"},
+            {"name": "explicit", "image": "docker.io/library/busybox"},
+            {"name": "quay", "image": "quay.io/foo/bar"},
+            {"name": "ruled", "image": "ghcr.io/foo/bar"}
+        ]));
+        let settings = Settings {
+            repos: HashMap::from([("ghcr.io".to_string(), "ghcr.example.com".into())]),
+            default_registry: Some("registry.example.com".to_string()),
+            ..Default::default()
+        };
+
+        let (pod, findings) = mutate_workload(pod, &settings).unwrap();
+        let images: Vec<String> = pod
+            .spec
+            .unwrap()
+            .containers
+            .into_iter()
+            .map(|c| c.image.unwrap())
+            .collect();
+        assert_eq!(
+            images,
+            vec![
+                "registry.example.com/library/alpine:3.19",
+                "registry.example.com/bitnami/nginx:latest",
+                "docker.io/library/busybox",
+                "quay.io/foo/bar",
+                "ghcr.example.com/foo/bar:latest",
+            ]
+        );
+        assert_eq!(findings[0].rule.as_deref(), Some("defaultRegistry"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn pull_secrets() -> Result<(), ()> {
+        let pod: apicore::Pod = serde_json::from_value(json!({
+            "metadata": {"name": "app"},
+            "spec": {
+                "containers": [
+                    {"name": "nginx", "image": "nginx"},
+                    {"name": "alpine", "image": "alpine"},
+                    {"name": "bar", "image": "quay.io/foo/bar"},
+                    {"name": "etcd", "image": "gcr.io/etcd"}
+                ],
+                "imagePullSecrets": [{"name": "existing"}, {"name": "quay-mirror"}]
+            }
+        }))
+        .unwrap();
+        let settings = Settings {
+            repos: HashMap::from([
+                ("docker.io".to_string(), "dockerhub.example.com".into()),
+                ("quay.io".to_string(), "quay.example.com".into()),
+                ("gcr.io".to_string(), "gcr.example.com".into()),
+            ]),
+            pull_secrets: HashMap::from([
+                (
+                    "dockerhub.example.com".to_string(),
+                    "dockerhub-mirror".to_string(),
+                ),
+                ("quay.example.com".to_string(), "quay-mirror".to_string()),
+            ]),
+            ..Default::default()
+        };
+
+        let (pod, _) = mutate_workload(pod, &settings).unwrap();
+        let secrets: Vec<String> = pod
+            .spec
+            .unwrap()
+            .image_pull_secrets
+            .unwrap()
+            .into_iter()
+            .map(|s| s.name)
+            .collect();
+        assert_eq!(secrets, vec!["existing", "quay-mirror", "dockerhub-mirror"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn pin_digests() -> Result<(), ()> {
+        let pod = pod(json!([
+            {"name": "nginx", "image": "nginx:1.25"},
+            {"name": "alpine", "image": "alpine@sha256:5678"}
+        ]));
+        let mut settings = Settings {
+            repos: HashMap::from([("docker.io".to_string(), "dockerhub.example.com".into())]),
+            pin_digest: true,
+            ..Default::default()
+        };
+
+        host::mock(host::MockHost {
+            digests: HashMap::from([
+                (
+                    "dockerhub.example.com/library/nginx:1.25".to_string(),
+                    "sha256:1234".to_string(),
+                ),
+                (
+                    "dockerhub.example.com/library/alpine@sha256:5678".to_string(),
+                    "sha256:5678".to_string(),
+                ),
+            ]),
+            ..Default::default()
+        });
+        let (mutated, findings) = mutate_workload(pod.clone(), &settings).unwrap();
+        assert_eq!(
+            images(&mutated),
+            vec![
+                "dockerhub.example.com/library/nginx@sha256:1234",
+                "dockerhub.example.com/library/alpine@sha256:5678",
+            ]
+        );
+        assert_eq!(
+            findings[0].rewritten.as_deref(),
+            Some("dockerhub.example.com/library/nginx@sha256:1234")
+        );
+
+        // offline, the tag is kept when failing open
+        host::mock(host::MockHost {
+            unavailable: Some("connection refused".to_string()),
+            ..Default::default()
+        });
+        let (mutated, _) = mutate_workload(pod.clone(), &settings).unwrap();
+        assert_eq!(
+            images(&mutated)[0],
+            "dockerhub.example.com/library/nginx:1.25"
+        );
+        settings.on_host_error = HostErrorPolicy::FailClosed;
+        assert!(mutate_workload(pod, &settings).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn verify_destination_exists() -> Result<(), ()> {
+        let pod = pod(json!([
+            {"name": "nginx", "image": "nginx:1.25"},
+            {"name": "alpine", "image": "alpine:3.19"}
+        ]));
+        let mut settings = Settings {
+            repos: HashMap::from([("docker.io".to_string(), "dockerhub.example.com".into())]),
+            verify_destination_exists: true,
+            ..Default::default()
+        };
+        host::mock(host::MockHost {
+            digests: HashMap::from([(
+                "dockerhub.example.com/library/nginx:1.25".to_string(),
+                "sha256:1234".to_string(),
+            )]),
+            ..Default::default()
+        });
+
+        let (mutated, findings) = mutate_workload(pod.clone(), &settings).unwrap();
+        let images: Vec<String> = mutated
+            .spec
+            .unwrap()
+            .containers
+            .into_iter()
+            .map(|c| c.image.unwrap())
+            .collect();
+        assert_eq!(
+            images,
+            vec!["dockerhub.example.com/library/nginx:1.25", "alpine:3.19"]
+        );
+        assert_eq!(findings[1].reason, Reason::MirrorMissing);
+        // the fallback requests no replication
+        assert!(mutated.metadata.annotations.is_none());
+
+        settings.on_missing = MissingPolicy::Reject;
+        let finding = mutate_workload(pod, &settings).unwrap_err();
+        assert_eq!(finding.container, "alpine");
+        assert_eq!(finding.reason, Reason::MirrorMissing);
+
+        Ok(())
+    }
+
+    #[test]
+    fn failover_destinations() -> Result<(), ()> {
+        let pod = pod(json!([
+            {"name": "nginx", "image": "nginx:1.25"},
+            {"name": "alpine", "image": "alpine:3.19"}
+        ]));
+        let mut settings: Settings = serde_json::from_value(json!({
+            "repos": {"docker.io": {
+                "destination": "harbor.example.com/dockerhub",
+                "failover": ["dockerhub.example.com"]
+            }}
+        }))
+        .unwrap();
+        host::mock(host::MockHost {
+            digests: HashMap::from([
+                (
+                    "harbor.example.com/dockerhub/library/nginx:1.25".to_string(),
+                    "sha256:1234".to_string(),
+                ),
+                (
+                    "dockerhub.example.com/library/alpine:3.19".to_string(),
+                    "sha256:5678".to_string(),
+                ),
+            ]),
+            ..Default::default()
+        });
+
+        let (mutated, findings) = mutate_workload(pod.clone(), &settings).unwrap();
+        let images: Vec<String> = mutated
+            .spec
+            .unwrap()
+            .containers
+            .into_iter()
+            .map(|c| c.image.unwrap())
+            .collect();
+        assert_eq!(
+            images,
+            vec![
+                "harbor.example.com/dockerhub/library/nginx:1.25",
+                "dockerhub.example.com/library/alpine:3.19"
+            ]
+        );
+        assert_eq!(
+            findings[1].rewritten.as_deref(),
+            Some("dockerhub.example.com/library/alpine:3.19")
+        );
+
+        host::mock(host::MockHost::default());
+        settings.on_missing = MissingPolicy::Reject;
+        let finding = mutate_workload(pod, &settings).unwrap_err();
+        assert_eq!(finding.container, "nginx");
+        assert_eq!(finding.reason, Reason::MirrorMissing);
+
+        Ok(())
+    }
+
+    #[test]
+    fn verify_signatures() -> Result<(), ()> {
+        let pod = |image: &str| pod(json!([{"name": "app", "image": image}]));
+        let settings: Settings = serde_json::from_value(json!({
+            "repos": {
+                "docker.io": "dockerhub.example.com",
+                "quay.io": "quay.example.com"
+            },
+            "signatures": [{"image": "dockerhub.example.com/*", "pubKeys": ["KEY"]}]
+        }))
+        .unwrap();
+        host::mock(host::MockHost {
+            signed: vec!["dockerhub.example.com/library/nginx:1.25".to_string()],
+            ..Default::default()
+        });
+
+        assert!(mutate_workload(pod("nginx:1.25"), &settings).is_ok());
+        let finding = mutate_workload(pod("alpine:3.19"), &settings).unwrap_err();
+        assert_eq!(finding.reason, Reason::UnverifiedSignature);
+        // no signature is required of the other mirrors
+        assert!(mutate_workload(pod("quay.io/foo/bar"), &settings).is_ok());
+
+        host::mock(host::MockHost {
+            unavailable: Some("connection refused".to_string()),
+            ..Default::default()
+        });
+        assert!(mutate_workload(pod("nginx:1.25"), &settings).is_err());
+        // failing open skips the verification, but never admits an
+        // image whose signature is checked and not trusted
+        let settings = Settings {
+            on_signature_host_error: HostErrorPolicy::FailOpen,
+            ..settings
+        };
+        assert!(mutate_workload(pod("nginx:1.25"), &settings).is_ok());
+        host::mock(host::MockHost::default());
+        let finding = mutate_workload(pod("alpine:3.19"), &settings).unwrap_err();
+        assert_eq!(finding.reason, Reason::UnverifiedSignature);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+//! Changes of the typed workloads carried over to the raw objects of the
+//! admission requests, so that the fields unknown to k8s-openapi, or
+//! normalized by its round-trip, are returned untouched.
+
+use serde_json::Value;
+
+/// Applies to `object` the changes between `before` and `after`, the
+/// serialized typed workload before and after its mutation. Fields equal in
+/// both are left untouched, fields missing from `after` are removed and
+/// fields unknown to the typed workload are kept, including in the array
+/// items matched by name when the arrays change length. Only the arrays of
+/// unnamed items shrinking are replaced as a whole.
+pub(crate) fn apply_changes(object: &mut Value, before: &Value, after: &Value) {
+    match (object, before, after) {
+        (Value::Object(object), Value::Object(before), Value::Object(after)) => {
+            object.retain(|key, _| !before.contains_key(key) || after.contains_key(key));
+            for (key, value) in after {
+                match (object.get_mut(key), before.get(key)) {
+                    (_, Some(previous)) if previous == value => {}
+                    (Some(field), Some(previous)) => apply_changes(field, previous, value),
+                    _ => {
+                        object.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        // items are matched by name when they have one, e.g. containers or
+        // volumes, so that the items inserted or removed leave the others
+        // untouched, and by position when only changed in place or appended
+        (Value::Array(object), Value::Array(before), Value::Array(after))
+            if object.len() == before.len() =>
+        {
+            let positions: Vec<Option<usize>> = match (names(before), names(after)) {
+                (Some(previous), Some(names)) => names
+                    .iter()
+                    .map(|name| previous.iter().position(|p| p == name))
+                    .collect(),
+                _ if before.len() <= after.len() => (0..after.len())
+                    .map(|i| (i < before.len()).then_some(i))
+                    .collect(),
+                _ => {
+                    *object = after.clone();
+                    return;
+                }
+            };
+            let mut items = std::mem::take(object);
+            *object = after
+                .iter()
+                .zip(positions)
+                .map(|(value, position)| match position {
+                    Some(i) => {
+                        let mut item = std::mem::take(&mut items[i]);
+                        apply_changes(&mut item, &before[i], value);
+                        item
+                    }
+                    None => value.clone(),
+                })
+                .collect();
+        }
+        (object, _, after) => *object = after.clone(),
+    }
+}
+
+/// Names of the `items` when they are all objects with a distinct `name`
+fn names(items: &[Value]) -> Option<Vec<&str>> {
+    let names: Vec<&str> = items
+        .iter()
+        .map(|item| item.get("name")?.as_str())
+        .collect::<Option<_>>()?;
+    let mut distinct = names.clone();
+    distinct.sort_unstable();
+    distinct.dedup();
+    (distinct.len() == names.len()).then_some(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn apply_changes_to_raw_object() -> Result<(), ()> {
+        let mut object = json!({
+            "metadata": {"name": "nginx", "managedFields": [{"manager": "kubectl"}]},
+            "spec": {
+                "containers": [
+                    {"name": "nginx", "image": "nginx", "futureField": {"enabled": true}},
+                    {"name": "sidecar", "image": "envoy", "resources": {}}
+                ],
+                "imagePullSecrets": [{"name": "existing"}]
+            },
+            "status": {"phase": "Pending"}
+        });
+        let before = json!({
+            "metadata": {"name": "nginx", "managedFields": [{"manager": "kubectl"}]},
+            "spec": {
+                "containers": [
+                    {"name": "nginx", "image": "nginx"},
+                    {"name": "sidecar", "image": "envoy"}
+                ],
+                "imagePullSecrets": [{"name": "existing"}]
+            },
+            "status": {"phase": "Pending"}
+        });
+        let after = json!({
+            "metadata": {"name": "nginx", "annotations": {"example.com/a": "b"}},
+            "spec": {
+                "containers": [
+                    {"name": "nginx", "image": "mirror.corp/nginx"},
+                    {"name": "sidecar", "image": "envoy"}
+                ],
+                "imagePullSecrets": [{"name": "existing"}, {"name": "mirror"}]
+            }
+        });
+
+        apply_changes(&mut object, &before, &after);
+        assert_eq!(
+            object,
+            json!({
+                "metadata": {"name": "nginx", "annotations": {"example.com/a": "b"}},
+                "spec": {
+                    "containers": [
+                        {"name": "nginx", "image": "mirror.corp/nginx", "futureField": {"enabled": true}},
+                        {"name": "sidecar", "image": "envoy", "resources": {}}
+                    ],
+                    "imagePullSecrets": [{"name": "existing"}, {"name": "mirror"}]
+                }
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn apply_changes_to_arrays_changing_length() -> Result<(), ()> {
+        let mut object = json!({
+            "initContainers": [{"name": "init", "image": "busybox", "futureField": 1}],
+            "containers": [
+                {"name": "nginx", "image": "nginx", "futureField": 2},
+                {"name": "sidecar", "image": "envoy", "futureField": 3}
+            ],
+            "args": ["--a", "--b"]
+        });
+        let before = json!({
+            "initContainers": [{"name": "init", "image": "busybox"}],
+            "containers": [
+                {"name": "nginx", "image": "nginx"},
+                {"name": "sidecar", "image": "envoy"}
+            ],
+            "args": ["--a", "--b"]
+        });
+        // an item inserted first, an item removed and unnamed items removed
+        let after = json!({
+            "initContainers": [
+                {"name": "setup", "image": "mirror.corp/setup"},
+                {"name": "init", "image": "mirror.corp/busybox"}
+            ],
+            "containers": [{"name": "sidecar", "image": "mirror.corp/envoy"}],
+            "args": ["--b"]
+        });
+
+        apply_changes(&mut object, &before, &after);
+        assert_eq!(
+            object,
+            json!({
+                "initContainers": [
+                    {"name": "setup", "image": "mirror.corp/setup"},
+                    {"name": "init", "image": "mirror.corp/busybox", "futureField": 1}
+                ],
+                "containers": [{"name": "sidecar", "image": "mirror.corp/envoy", "futureField": 3}],
+                "args": ["--b"]
+            })
+        );
+
+        // duplicate names are matched by position
+        let mut object = json!([{"name": "a", "x": 1}, {"name": "a", "x": 2}]);
+        let before = json!([{"name": "a"}, {"name": "a"}]);
+        let after = json!([{"name": "a"}, {"name": "a", "y": 3}, {"name": "b"}]);
+        apply_changes(&mut object, &before, &after);
+        assert_eq!(
+            object,
+            json!([{"name": "a", "x": 1}, {"name": "a", "x": 2, "y": 3}, {"name": "b"}])
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::hash_map::HashMap;
+
+/// Names of the built-in mirror presets, selectable with `preset: <name>`
+pub(crate) const PRESETS: &[&str] = &["daocloud", "harbor", "tencentcloud"];
+
+/// Upstream registries covered by the presets, along with the name of the
+/// project a Harbor proxy cache conventionally uses for each of them
+const HARBOR_PROJECTS: &[(&str, &str)] = &[
+    ("docker.io", "dockerhub"),
+    ("gcr.io", "gcr"),
+    ("ghcr.io", "ghcr"),
+    ("quay.io", "quay"),
+    ("registry.k8s.io", "k8s"),
+    ("k8s.gcr.io", "k8s"),
+];
+
+/// Expands a preset into its source to destination mapping.
+///
+/// `registry` is the host of the mirror, only required by presets that do
+/// not target a public mirror, like `harbor`.
+pub(crate) fn expand(
+    name: &str,
+    registry: Option<&str>,
+) -> Result<HashMap<String, String>, String> {
+    let repos = match name {
+        "daocloud" => HARBOR_PROJECTS
+            .iter()
+            .map(|(src, _)| (src.to_string(), format!("m.daocloud.io/{}", src)))
+            .collect(),
+        "harbor" => {
+            let registry = registry
+                .ok_or_else(|| "preset harbor requires presetRegistry to be set".to_string())?;
+            HARBOR_PROJECTS
+                .iter()
+                .map(|(src, project)| (src.to_string(), format!("{}/{}", registry, project)))
+                .collect()
+        }
+        "tencentcloud" => HashMap::from([
+            (
+                "docker.io".to_string(),
+                "dockerhub.tencentcloudcr.com".to_string(),
+            ),
+            ("gcr.io".to_string(), "gcr.tencentcloudcr.com".to_string()),
+            ("quay.io".to_string(), "quay.tencentcloudcr.com".to_string()),
+            (
+                "k8s.gcr.io".to_string(),
+                "k8s.tencentcloudcr.com".to_string(),
+            ),
+        ]),
+        _ => {
+            return Err(format!(
+                "unknown preset {}, expected one of: {}",
+                name,
+                PRESETS.join(", ")
+            ))
+        }
+    };
+    Ok(repos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_presets() {
+        for name in PRESETS {
+            let repos = expand(name, Some("harbor.example.com")).unwrap();
+            assert!(repos.contains_key("docker.io"), "preset {}", name);
+        }
+
+        assert_eq!(
+            expand("daocloud", None).unwrap()["quay.io"],
+            "m.daocloud.io/quay.io"
+        );
+        assert_eq!(
+            expand("harbor", Some("harbor.example.com")).unwrap()["docker.io"],
+            "harbor.example.com/dockerhub"
+        );
+        assert!(expand("harbor", None).is_err());
+        assert!(expand("unknown", None).is_err());
+    }
+}
@@ -0,0 +1,181 @@
+//! Structured results of the evaluation of a pod, with stable fields so that
+//! Kubewarden audit results map cleanly into PolicyReport resources.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::settings::Attribution;
+use crate::ANNOTATION_PREFIX;
+
+use serde::Serialize;
+
+/// Why an image has been reported
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum Reason {
+    /// The image has been rewritten to the mirror
+    Rewritten,
+    /// The image contains an unresolved template placeholder
+    TemplatePlaceholder,
+    /// The rewritten image cannot be found at the mirror
+    MirrorMissing,
+    /// The rewritten image could not be checked, a host capability failed
+    HostCapabilityError,
+    /// The image matches no rule and is not pulled from an allowed registry
+    NoMatchingRule,
+    /// The rewritten image does not carry the required signatures
+    UnverifiedSignature,
+    /// The image is not a valid reference
+    InvalidReference,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::Rewritten => write!(f, "rewritten"),
+            Reason::TemplatePlaceholder => write!(f, "unresolved template placeholder"),
+            Reason::MirrorMissing => write!(f, "missing at the mirror"),
+            Reason::HostCapabilityError => write!(f, "host capability error"),
+            Reason::NoMatchingRule => write!(f, "no matching rule for this registry"),
+            Reason::UnverifiedSignature => write!(f, "signature verification failed"),
+            Reason::InvalidReference => write!(f, "invalid image reference"),
+        }
+    }
+}
+
+/// Result of the evaluation of a single image
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Finding {
+    /// Identifier of the rule matching the image, e.g. `repos/docker.io`
+    pub rule: Option<String>,
+    /// Name of the container, or of the volume, using the image
+    pub container: String,
+    /// Image as found in the pod
+    pub original: String,
+    /// Image pulled from the mirror
+    pub rewritten: Option<String>,
+    pub reason: Reason,
+    /// Owners of the matching rule
+    #[serde(flatten)]
+    pub attribution: Attribution,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.container, self.original)?;
+        if let Some(rewritten) = &self.rewritten {
+            write!(f, " -> {}", rewritten)?;
+        }
+        write!(f, " ({}", self.reason)?;
+        if let Some(rule) = &self.rule {
+            write!(f, ", rule {}", rule)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Returns the audit annotations describing `findings`, if any
+pub(crate) fn audit_annotations(findings: &[Finding]) -> Option<HashMap<String, String>> {
+    if findings.is_empty() {
+        return None;
+    }
+    Some(HashMap::from([(
+        format!("{}/results", ANNOTATION_PREFIX),
+        serde_json::to_string(findings).unwrap_or_default(),
+    )]))
+}
+
+/// Returns the admission warnings describing the rewritten images of
+/// `findings`, one per container, if any
+pub(crate) fn warnings(findings: &[Finding]) -> Option<Vec<String>> {
+    let warnings: Vec<String> = findings
+        .iter()
+        .filter(|finding| finding.reason == Reason::Rewritten)
+        .filter_map(|finding| {
+            let rewritten = finding.rewritten.as_ref()?;
+            Some(format!(
+                "container {:?}: image rewritten from {} to {}",
+                finding.container, finding.original, rewritten
+            ))
+        })
+        .collect();
+    if warnings.is_empty() {
+        None
+    } else {
+        Some(warnings)
+    }
+}
+
+/// Returns the rejection message listing the images of `findings` that are
+/// not pulled from a mirror yet, if any
+pub(crate) fn violations(findings: &[Finding]) -> Option<String> {
+    let violations: Vec<String> = findings
+        .iter()
+        .filter_map(|finding| {
+            let rewritten = finding.rewritten.as_ref()?;
+            Some(format!(
+                "container {:?} uses {} instead of {}",
+                finding.container, finding.original, rewritten
+            ))
+        })
+        .collect();
+    if violations.is_empty() {
+        None
+    } else {
+        Some(format!(
+            "images not pulled from an approved mirror: {}",
+            violations.join("; ")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finding_fields() {
+        let finding = Finding {
+            rule: Some("repos/docker.io".to_string()),
+            container: "nginx".to_string(),
+            original: "nginx".to_string(),
+            rewritten: Some("mirror.example.com/library/nginx:latest".to_string()),
+            reason: Reason::Rewritten,
+            attribution: Attribution {
+                team: Some("platform".to_string()),
+                cost_center: None,
+            },
+        };
+        assert_eq!(
+            finding.to_string(),
+            "nginx: nginx -> mirror.example.com/library/nginx:latest (rewritten, rule repos/docker.io)"
+        );
+
+        assert_eq!(
+            warnings(std::slice::from_ref(&finding)),
+            Some(vec![String::from(
+                "container \"nginx\": image rewritten from nginx to \
+                 mirror.example.com/library/nginx:latest"
+            )])
+        );
+
+        let annotations = audit_annotations(&[finding]).unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(
+                &annotations["mutate-repos-policy.kubewarden.io/results"]
+            )
+            .unwrap(),
+            serde_json::json!([{
+                "rule": "repos/docker.io",
+                "container": "nginx",
+                "original": "nginx",
+                "rewritten": "mirror.example.com/library/nginx:latest",
+                "reason": "rewritten",
+                "team": "platform"
+            }])
+        );
+        assert!(audit_annotations(&[]).is_none());
+        assert!(warnings(&[]).is_none());
+    }
+}
@@ -0,0 +1,66 @@
+//! JSON Schema of the settings, so that `kwctl`, the Kubewarden UI or editors
+//! validate and complete the settings before the policy is deployed.
+
+use guest::prelude::*;
+use kubewarden_policy_sdk::wapc_guest as guest;
+
+use crate::settings::Settings;
+
+/// Returns the JSON Schema of the settings, derived from their types
+pub(crate) fn generate() -> serde_json::Result<String> {
+    serde_json::to_string_pretty(&schemars::schema_for!(Settings))
+}
+
+/// waPC guest function to register under the name `settings_schema`
+pub(crate) fn settings_schema(_payload: &[u8]) -> CallResult {
+    Ok(generate()?.into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Schema shipped along with the policy, regenerated by
+    /// `UPDATE_SCHEMA=1 cargo test`
+    const SCHEMA_FILE: &str = "settings-schema.json";
+
+    #[test]
+    fn schema_is_up_to_date() -> Result<(), ()> {
+        let schema = generate().unwrap() + "\n";
+        if std::env::var_os("UPDATE_SCHEMA").is_some() {
+            std::fs::write(SCHEMA_FILE, &schema).unwrap();
+        }
+        assert_eq!(
+            std::fs::read_to_string(SCHEMA_FILE).unwrap_or_default(),
+            schema,
+            "{} is outdated, run UPDATE_SCHEMA=1 cargo test",
+            SCHEMA_FILE
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn schema_is_referenced_by_the_metadata() -> Result<(), ()> {
+        for metadata in [
+            include_str!("../metadata.yml"),
+            include_str!("../artifacthub-pkg.yml"),
+        ] {
+            assert!(metadata.contains(SCHEMA_FILE));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn schema_describes_the_settings() -> Result<(), ()> {
+        let schema: serde_json::Value = serde_json::from_str(&generate().unwrap()).unwrap();
+        let properties = &schema["properties"];
+        assert!(properties["repos"].is_object());
+        assert!(properties["rules"].is_object());
+        assert!(properties["matchStrategy"].is_object());
+        assert!(schema["definitions"]["Rule"].is_object());
+
+        Ok(())
+    }
+}
@@ -1,24 +1,1492 @@
-use std::collections::hash_map::HashMap;
+use std::collections::{hash_map::HashMap, BTreeMap};
+use std::fmt;
 
-use crate::LOG_DRAIN;
+use crate::image::{self, ImageRef};
+use crate::{jsonpath, presets, template, ANNOTATION_PREFIX, LOG_DRAIN};
 
+use regex::Regex;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use slog::info;
 
+/// Returns the annotation selecting a mirror set
+pub(crate) fn mirror_annotation() -> String {
+    format!("{}/mirror", ANNOTATION_PREFIX)
+}
+
+/// Image pull policies of the containers
+const PULL_POLICIES: &[&str] = &["Always", "IfNotPresent", "Never"];
+
 // Describe the settings your policy expects when
 // loaded by the policy server.
-#[derive(Serialize, Deserialize, Default, Debug)]
-#[serde(default)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+#[serde(default, rename_all = "camelCase")]
 pub(crate) struct Settings {
-    pub repos: HashMap<String, String>,
+    /// Mapping of source prefixes to destinations, the legacy form of
+    /// `rules`. Once merged with the other layers, its entries are converted
+    /// into `rules` entries, see [`Settings::effective_rules`]
+    pub repos: HashMap<String, Destination>,
+    /// Ordered list of rewrite rules, the matching ones being applied
+    /// according to `match_strategy` and their priority
+    pub rules: Vec<Rule>,
+    /// Rules applied to an image among the matching ones, the one with the
+    /// longest source by default
+    pub match_strategy: MatchStrategy,
+    /// Reject pods whose images contain unrendered `{{ ... }}` or `${...}`
+    /// placeholders instead of leaving them untouched
+    pub reject_templated_images: bool,
+    /// Treat `k8s.gcr.io` and its regional variants as `registry.k8s.io`,
+    /// so a single rule covers every Kubernetes registry hostname
+    pub unify_k8s_registries: bool,
+    /// Mapping of registry aliases to the registry they serve, applied to
+    /// the images and the sources before matching, on top of the Docker Hub
+    /// aliases, e.g. `index.docker.io`
+    pub registry_aliases: HashMap<String, String>,
+    /// Name of a built-in mirror preset expanded into `repos`, entries of
+    /// `repos` take precedence over the preset ones
+    pub preset: Option<String>,
+    /// Host of the mirror used by presets that need one, e.g. `harbor`
+    pub preset_registry: Option<String>,
+    /// Look rewritten images up at the mirror, keeping the original image
+    /// and annotating the pod for an external replicator when missing
+    pub request_replication: bool,
+    /// Mapping of organizations, the first component of the repository path,
+    /// to destinations, regardless of the registry of the image, ordered
+    /// like the source `*/<org>`
+    pub orgs: HashMap<String, Destination>,
+    /// Break-glass annotation: pods carrying it with a non-empty value are
+    /// admitted without mutation, with an audit log record and a warning
+    pub bypass_annotation: Option<String>,
+    /// Organization-wide baseline mapping of repos, overridden by `repos`
+    pub baseline: HashMap<String, Destination>,
+    /// Reject settings where layers map the same source to different
+    /// destinations, instead of applying the precedence silently
+    pub fail_on_conflicts: bool,
+    /// Operating systems of the pods left untouched, e.g. `windows`
+    pub excluded_os: Vec<String>,
+    /// Mapping of images, regardless of their tag, to the approved images
+    /// replacing them in ephemeral containers added by `kubectl debug`,
+    /// sorted so that the first of the images naming the same repository
+    /// wins
+    pub debug_images: BTreeMap<String, String>,
+    /// Behavior when a host capability call fails, e.g. the registry is down
+    pub on_host_error: HostErrorPolicy,
+    /// Behavior when the sigstore host capability fails while verifying the
+    /// `signatures`, failing closed by default
+    #[serde(default = "fail_closed")]
+    pub on_signature_host_error: HostErrorPolicy,
+    /// Tag written into rewritten images admitted without tag nor digest,
+    /// instead of the implicit `latest`
+    pub default_tag: Option<String>,
+    /// Annotation, or label, set to `true` on a workload or its pods to
+    /// exempt them from mutation, `mutate-repos-policy.kubewarden.io/skip`
+    /// by default
+    pub skip_annotation: Option<String>,
+    /// Record the original images of the rewritten containers in an
+    /// annotation of the pods, to trace and roll back the mutation
+    pub record_original_image: bool,
+    /// Summarize the rewritten images in an annotation of the pods, shown by
+    /// `kubectl describe`, so that application teams see why their images
+    /// differ from their manifests
+    pub record_mutation_summary: bool,
+    /// Inject the original image, in an environment variable, into the
+    /// containers whose image is rewritten
+    pub inject_original_image: bool,
+    /// Environment variable injected by `inject_original_image`,
+    /// `KW_ORIGINAL_IMAGE` by default
+    pub original_image_env_var: Option<String>,
+    /// Return an admission warning for every rewritten image, surfacing the
+    /// mutation to the API client, e.g. in the `kubectl apply` output
+    pub emit_warnings: bool,
+    /// Whether the images are rewritten, or the pods whose images would be
+    /// rewritten are rejected
+    pub enforce: EnforceMode,
+    /// Compute and log the rewrites, and the rejections, without applying
+    /// them: every request is accepted unmodified
+    pub dry_run: bool,
+    /// Reject the images matching no rule, unless pulled from a mirror or
+    /// from one of the `passthrough_registries`
+    pub reject_on_no_match: bool,
+    /// Registries whose images are admitted by `reject_on_no_match`
+    pub passthrough_registries: Vec<String>,
+    /// Registry of the short-form images, naming no registry, matching no
+    /// rule, e.g. `alpine:3.19`, instead of the implicit Docker Hub
+    pub default_registry: Option<String>,
+    /// Mapping of destination registries to the pull secrets added to the
+    /// pods whose images are rewritten to them
+    pub pull_secrets: HashMap<String, String>,
+    /// Reference the rewritten images by the digest of their manifest at the
+    /// mirror, resolved with the OCI host capability, instead of their tag
+    pub pin_digest: bool,
+    /// Look the rewritten images up at the mirror, with the OCI host
+    /// capability, and apply `on_missing` to the missing ones
+    pub verify_destination_exists: bool,
+    /// Behavior when `verify_destination_exists` finds a missing image
+    pub on_missing: MissingPolicy,
+    /// Sigstore signatures required of the rewritten images, in the format
+    /// of the Kubewarden image signature verification policies
+    pub signatures: Vec<Signature>,
+    /// Reject the images that are not valid references, instead of leaving
+    /// them untouched
+    pub reject_invalid_images: bool,
+    /// Check the images against the whole OCI distribution reference
+    /// grammar, including the length of the names and the algorithm and
+    /// encoding of the digests
+    pub strict_image_references: bool,
+    /// Rewrite the images of the containers of the pods, `true` by default
+    #[serde(default = "default_true")]
+    pub mutate_containers: bool,
+    /// Rewrite the images of the init containers, `true` by default
+    #[serde(default = "default_true")]
+    pub mutate_init_containers: bool,
+    /// Rewrite the images of the ephemeral containers, `true` by default
+    #[serde(default = "default_true")]
+    pub mutate_ephemeral_containers: bool,
+    /// Names of the containers mutated, per list of containers of the pods
+    pub container_filters: ContainerFilters,
+    /// Pull policy set on the containers whose image is rewritten, e.g.
+    /// `IfNotPresent` to reduce the load of the mirror
+    pub set_image_pull_policy: Option<String>,
+    /// Images never rewritten, regardless of the rules: whole references,
+    /// e.g. `registry.k8s.io/pause:3.9`, prefixes or globs
+    pub exclude_images: Vec<String>,
+    /// Globs of the names of the environment variables of the containers
+    /// holding images to rewrite, e.g. `RELATED_IMAGE_*`
+    pub image_env_vars: Vec<String>,
+    /// Globs of the flags of the arguments of the containers holding images
+    /// to rewrite, e.g. `--sidecar-image`
+    pub image_args: Vec<String>,
+    /// Globs of the keys of the pod annotations holding images to rewrite,
+    /// e.g. `sidecar.istio.io/proxyImage`, read by sidecar injectors
+    pub image_annotations: Vec<String>,
+    /// Admission operations the policy mutates, `CREATE` only when unset
+    pub operations: Option<Vec<Operation>>,
+    /// Which updates are mutated, when `operations` includes `UPDATE`
+    pub on_update: UpdatePolicy,
+    /// Leave the images referenced by digest untouched, as their digest may
+    /// not exist at the mirror under the rewritten name
+    pub skip_digest_references: bool,
+    /// OCI artifact, e.g. an organization-wide rule set published to the
+    /// registry, whose manifest annotations hold `repos` and `rules` loaded
+    /// at evaluation time, with the OCI host capability
+    pub mappings_artifact: Option<String>,
+    /// Mapping of repos loaded from the `mappings_artifact`, overriding
+    /// `baseline`
+    #[serde(skip)]
+    pub artifact_repos: HashMap<String, Destination>,
+    /// ConfigMap whose `repos` and `rules` keys are loaded at evaluation
+    /// time, with the context-aware Kubernetes host capability, on top of
+    /// the ones of the settings
+    pub mappings_config_map: Option<ConfigMapRef>,
+    /// Mapping of repos loaded from the `mappings_config_map`, overriding
+    /// `repos`
+    #[serde(skip)]
+    pub config_map_repos: HashMap<String, Destination>,
+    /// Mapping of the `group/version/Kind` of custom resources, or
+    /// `version/Kind` for the core group, to the JSON paths of the images
+    /// they embed, e.g. `.spec.image`
+    pub custom_resources: HashMap<String, Vec<String>>,
+    /// Mappings of namespaces to the mappings overriding the global ones for
+    /// their objects, e.g. to route every team to its own mirror project
+    pub namespace_overrides: HashMap<String, Mappings>,
+    /// Named mappings, overriding the global ones for the objects, or pods,
+    /// selecting them with the mirror annotation, e.g. a regional mirror
+    pub mirror_sets: HashMap<String, Mappings>,
+}
+
+/// Repos and rules overriding the global ones
+#[derive(Serialize, Deserialize, JsonSchema, Default, Debug, Clone, PartialEq, Eq)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct Mappings {
+    /// Mapping of source prefixes to destinations, overriding the global
+    /// entries of the same source
+    pub repos: HashMap<String, Destination>,
+    /// Rules tried before the global rules list
+    pub rules: Vec<Rule>,
+}
+
+/// Reference to a ConfigMap
+#[derive(Serialize, Deserialize, JsonSchema, Default, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ConfigMapRef {
+    pub namespace: String,
+    pub name: String,
+}
+
+impl fmt::Display for ConfigMapRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.namespace, self.name)
+    }
+}
+
+/// Filters of the names of the containers mutated, applying independently
+/// to each list of containers of the pods
+#[derive(Serialize, Deserialize, JsonSchema, Default, Debug, Clone, PartialEq, Eq)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct ContainerFilters {
+    pub containers: NameFilter,
+    pub init_containers: NameFilter,
+    pub ephemeral_containers: NameFilter,
+}
+
+/// Globs of names, where `*` matches any sequence of characters
+#[derive(Serialize, Deserialize, JsonSchema, Default, Debug, Clone, PartialEq, Eq)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct NameFilter {
+    /// Only mutate the containers whose name matches one of these globs,
+    /// every container when empty
+    pub include: Vec<String>,
+    /// Never mutate the containers whose name matches one of these globs,
+    /// e.g. `istio-proxy`
+    pub exclude: Vec<String>,
+}
+
+impl NameFilter {
+    /// Determines if the container `name` is mutated
+    pub fn allows(&self, name: &str) -> bool {
+        let matches = |glob: &String| image::wildcard_match(glob, name);
+        (self.include.is_empty() || self.include.iter().any(matches))
+            && !self.exclude.iter().any(matches)
+    }
+}
+
+/// Admission operation of a request
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub(crate) enum Operation {
+    Create,
+    Update,
+}
+
+/// Updates of the objects that are mutated
+#[derive(Serialize, Deserialize, JsonSchema, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum UpdatePolicy {
+    /// Every update
+    #[default]
+    Always,
+    /// Only the updates changing the images of the object, leaving alone the
+    /// controllers re-applying the original images on unrelated updates
+    ImagesChanged,
+}
+
+/// Behavior when a host capability call fails mid-evaluation
+#[derive(Serialize, Deserialize, JsonSchema, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum HostErrorPolicy {
+    /// Proceed without the check backed by the host capability
+    #[default]
+    FailOpen,
+    /// Reject the request
+    FailClosed,
+}
+
+/// Sigstore signatures required of the rewritten images matching `image`
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub(crate) enum Signature {
+    /// Signed with one of the public keys
+    #[serde(rename_all = "camelCase")]
+    PubKeys {
+        image: String,
+        pub_keys: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        annotations: Option<HashMap<String, String>>,
+    },
+    /// Signed keyless by one of the identities
+    #[serde(rename_all = "camelCase")]
+    Keyless {
+        image: String,
+        keyless: Vec<KeylessIdentity>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        annotations: Option<HashMap<String, String>>,
+    },
+    /// Signed keyless by a subject starting with one of the URL prefixes
+    #[serde(rename_all = "camelCase")]
+    KeylessPrefix {
+        image: String,
+        keyless_prefix: Vec<KeylessPrefix>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        annotations: Option<HashMap<String, String>>,
+    },
+    /// Signed keyless by a GitHub Actions workflow
+    #[serde(rename_all = "camelCase")]
+    GithubActions {
+        image: String,
+        github_actions: GithubActions,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        annotations: Option<HashMap<String, String>>,
+    },
+}
+
+/// Identity of a keyless signer
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct KeylessIdentity {
+    pub issuer: String,
+    pub subject: String,
+}
+
+/// Identity of keyless signers whose subject starts with `url_prefix`
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct KeylessPrefix {
+    pub issuer: String,
+    pub url_prefix: String,
+}
+
+/// GitHub Actions workflows signing keyless
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct GithubActions {
+    pub owner: String,
+    #[serde(default)]
+    pub repo: Option<String>,
+}
+
+impl Signature {
+    /// Returns the glob of the images the signature is required of
+    pub fn image(&self) -> &str {
+        match self {
+            Signature::PubKeys { image, .. }
+            | Signature::Keyless { image, .. }
+            | Signature::KeylessPrefix { image, .. }
+            | Signature::GithubActions { image, .. } => image,
+        }
+    }
+}
+
+/// Behavior when a rewritten image is missing at the mirror
+#[derive(Serialize, Deserialize, JsonSchema, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum MissingPolicy {
+    /// Keep the original image
+    #[default]
+    Fallback,
+    /// Reject the request
+    Reject,
+}
+
+/// How the settings are enforced
+#[derive(Serialize, Deserialize, JsonSchema, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum EnforceMode {
+    /// Rewrite the images to the mirror
+    #[default]
+    Mutate,
+    /// Reject the images that would be rewritten, without mutation
+    Validate,
+}
+
+/// Layers of repo mappings, listed from the lowest to the highest precedence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Layer {
+    Preset,
+    Baseline,
+    Artifact,
+    Repos,
+    ConfigMap,
+}
+
+impl fmt::Display for Layer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Layer::Preset => write!(f, "preset"),
+            Layer::Baseline => write!(f, "baseline"),
+            Layer::Artifact => write!(f, "mappingsArtifact"),
+            Layer::Repos => write!(f, "repos"),
+            Layer::ConfigMap => write!(f, "mappingsConfigMap"),
+        }
+    }
+}
+
+/// Destination of a repo mapping: either a plain prefix, or a prefix along
+/// with conditions restricting the images it applies to
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub(crate) enum Destination {
+    Prefix(String),
+    Conditional(Box<Target>),
+}
+
+/// Destination prefix along with the conditions restricting the images it
+/// applies to
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Target {
+    /// Destination prefix, unset when stripping the registry
+    #[serde(default)]
+    pub destination: String,
+    /// How the matched images are rewritten
+    #[serde(default, skip_serializing_if = "Action::is_replace")]
+    pub action: Action,
+    /// Rules of higher priority are tried first, `0` by default
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub priority: i32,
+    /// Destinations tried in order, with the manifest host capability, when
+    /// the image is missing at the destination
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failover: Vec<String>,
+    #[serde(flatten)]
+    pub conditions: Conditions,
+    #[serde(flatten)]
+    pub attribution: Attribution,
+    #[serde(flatten)]
+    pub tag_rewrite: TagRewrite,
+    /// Drop the `library/` component of the Docker Hub official images from
+    /// the rewritten images, e.g. `mirror.corp/alpine` for `alpine`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub strip_library_prefix: bool,
+}
+
+/// How a rule rewrites the images it matches
+#[derive(Serialize, Deserialize, JsonSchema, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum Action {
+    /// Replace the matched components by the destination
+    #[default]
+    Replace,
+    /// Drop the registry, e.g. `gcr.io/foo/bar:v1` to `foo/bar:v1`, leaving
+    /// the registry mirrors of the container runtime of the nodes to resolve
+    /// the short name
+    StripRegistry,
+}
+
+impl Action {
+    fn is_replace(&self) -> bool {
+        *self == Action::Replace
+    }
+}
+
+/// Determines if the priority of a rule is the default one
+fn is_zero(priority: &i32) -> bool {
+    *priority == 0
+}
+
+/// Rules applied to an image, among the matching ones
+#[derive(Serialize, Deserialize, JsonSchema, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum MatchStrategy {
+    /// The first one, in the order of the rules
+    FirstMatch,
+    /// The one with the longest source
+    #[default]
+    LongestMatch,
+    /// Every one, in the order of the rules, each one rewriting the image
+    /// rewritten by the previous ones
+    All,
+}
+
+/// Conditions restricting the images a rule applies to
+#[derive(Serialize, Deserialize, JsonSchema, Default, Debug, Clone, PartialEq, Eq)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct Conditions {
+    /// Only apply to images whose repository path has this many components,
+    /// e.g. `1` for `gcr.io/etcd` but not `gcr.io/google/etcd`
+    pub path_depth: Option<usize>,
+    /// Only apply to Docker Hub official images (`docker.io/library/*`)
+    pub official_only: bool,
+    /// Only apply to pods running on this operating system, e.g. `windows`
+    pub os: Option<String>,
+    /// Only apply to images whose tag matches this regular expression as a
+    /// whole, e.g. `v1\..*`, never to images referenced by digest only
+    pub tag_pattern: Option<String>,
+}
+
+/// Entry of the ordered list of rewrite rules
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub(crate) enum Rule {
+    /// Replaces the `source` prefix of the normalized images
+    Prefix {
+        source: String,
+        #[serde(flatten)]
+        target: Target,
+    },
+    /// Replaces the normalized images matching the `match` regular
+    /// expression, `replace` can reference its capture groups, e.g. `$1`
+    Regex {
+        #[serde(rename = "match")]
+        pattern: String,
+        replace: String,
+        #[serde(flatten)]
+        conditions: Conditions,
+        #[serde(flatten)]
+        attribution: Attribution,
+        #[serde(flatten)]
+        tag_rewrite: TagRewrite,
+        #[serde(default, skip_serializing_if = "is_zero")]
+        priority: i32,
+    },
+    /// Replaces the images equal to the `exact` reference, tag or digest
+    /// included, with the `replace` reference, before any other rule
+    Exact {
+        exact: String,
+        replace: String,
+        #[serde(flatten)]
+        conditions: Conditions,
+        #[serde(flatten)]
+        attribution: Attribution,
+    },
+}
+
+/// Rewriting of the tags of the images rewritten by a rule
+#[derive(Serialize, Deserialize, JsonSchema, Default, Debug, Clone, PartialEq, Eq)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct TagRewrite {
+    /// Mapping of tags to the tags replacing them, e.g. `latest` to a fixed
+    /// version
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub tags: HashMap<String, String>,
+    /// Prefix added to the tags missing from `tags`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag_prefix: Option<String>,
+    /// Suffix added to the tags missing from `tags`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag_suffix: Option<String>,
+}
+
+/// Tags attributing the images rewritten by a rule to their owners
+#[derive(Serialize, Deserialize, JsonSchema, Default, Debug, Clone, PartialEq, Eq)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct Attribution {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub team: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost_center: Option<String>,
+}
+
+impl Destination {
+    /// Returns the prefix replacing the source of the mapping
+    pub fn prefix(&self) -> &str {
+        match self {
+            Destination::Prefix(destination) => destination,
+            Destination::Conditional(target) => &target.destination,
+        }
+    }
+
+    /// Returns the destinations tried when the image is missing at the
+    /// destination
+    pub fn failover(&self) -> &[String] {
+        match self {
+            Destination::Prefix(_) => &[],
+            Destination::Conditional(target) => &target.failover,
+        }
+    }
+
+    /// Returns the priority of the mapping
+    pub fn priority(&self) -> i32 {
+        match self {
+            Destination::Prefix(_) => 0,
+            Destination::Conditional(target) => target.priority,
+        }
+    }
+
+    /// Returns the tags attributing the rewritten images to their owners
+    pub fn attribution(&self) -> Attribution {
+        match self {
+            Destination::Prefix(_) => Attribution::default(),
+            Destination::Conditional(target) => target.attribution.clone(),
+        }
+    }
+
+    /// Returns how the matched images are rewritten
+    pub fn action(&self) -> Action {
+        match self {
+            Destination::Prefix(_) => Action::Replace,
+            Destination::Conditional(target) => target.action,
+        }
+    }
+
+    /// Determines if the `library/` component of the Docker Hub official
+    /// images is dropped from the rewritten images
+    pub fn strip_library_prefix(&self) -> bool {
+        match self {
+            Destination::Prefix(_) => false,
+            Destination::Conditional(target) => target.strip_library_prefix,
+        }
+    }
+
+    /// Rewrites the tag of an image according to the destination, images
+    /// referenced by digest being left untouched
+    pub fn retag(&self, image: &mut ImageRef) {
+        if let Destination::Conditional(target) = self {
+            target.tag_rewrite.apply(image);
+        }
+    }
+
+    /// Determines if the conditions of the destination hold for an image of
+    /// a pod running on the `os` operating system, `tag_regex` being its
+    /// compiled tag pattern, see [`Destination::tag_regex`]
+    pub fn applies_to(&self, image: &ImageRef, os: &str, tag_regex: Option<&Regex>) -> bool {
+        match self {
+            Destination::Prefix(_) => true,
+            Destination::Conditional(target) => target.conditions.hold(image, os, tag_regex),
+        }
+    }
+
+    /// Compiles the tag pattern of the conditions of the destination, if any
+    pub fn tag_regex(&self) -> Option<Result<Regex, String>> {
+        match self {
+            Destination::Prefix(_) => None,
+            Destination::Conditional(target) => target.conditions.tag_regex(),
+        }
+    }
+
+    /// Returns the destination as the target of a rule, a plain prefix
+    /// having neither conditions nor tag rewrite
+    pub fn into_target(self) -> Target {
+        match self {
+            Destination::Prefix(destination) => Target {
+                destination,
+                action: Action::Replace,
+                priority: 0,
+                failover: Vec::new(),
+                conditions: Conditions::default(),
+                attribution: Attribution::default(),
+                tag_rewrite: TagRewrite::default(),
+                strip_library_prefix: false,
+            },
+            Destination::Conditional(target) => *target,
+        }
+    }
+}
+
+impl Target {
+    /// Checks the destination and the failover destinations, which must be
+    /// unset when stripping the registry
+    fn validate_destination(&self) -> Result<(), String> {
+        for destination in &self.failover {
+            validate_destination(destination).map_err(|e| format!("failover: {}", e))?;
+        }
+        match self.action {
+            Action::Replace => validate_destination(&self.destination),
+            Action::StripRegistry if !self.failover.is_empty() => {
+                Err("a stripRegistry rule cannot have failover destinations".to_string())
+            }
+            Action::StripRegistry if self.destination.is_empty() => Ok(()),
+            Action::StripRegistry => Err(format!(
+                "destination {} of a stripRegistry rule must be unset",
+                self.destination
+            )),
+        }
+    }
+}
+
+impl Conditions {
+    /// Determines if the conditions hold for an image of a pod running on the
+    /// `os` operating system. The `tag_pattern` is matched by `tag_regex`,
+    /// compiled once with [`Conditions::tag_regex`] rather than per image.
+    pub fn hold(&self, image: &ImageRef, os: &str, tag_regex: Option<&Regex>) -> bool {
+        self.path_depth
+            .is_none_or(|depth| image.path_depth() == depth)
+            && (!self.official_only || image.is_official())
+            && self.os.as_deref().is_none_or(|cond_os| cond_os == os)
+            && (self.tag_pattern.is_none()
+                || tag_regex.is_some_and(|regex| {
+                    image.tag.as_deref().is_some_and(|tag| regex.is_match(tag))
+                }))
+    }
+
+    /// Compiles the `tag_pattern`, anchored to match whole tags, if any
+    pub fn tag_regex(&self) -> Option<Result<Regex, String>> {
+        let pattern = self.tag_pattern.as_ref()?;
+        Some(
+            Regex::new(&format!("^(?:{})$", pattern))
+                .map_err(|e| format!("invalid tag pattern {}: {}", pattern, e)),
+        )
+    }
+
+    /// Checks that the conditions can be evaluated
+    fn validate(&self) -> Result<(), String> {
+        self.tag_regex().transpose().map(|_| ())
+    }
+}
+
+impl TagRewrite {
+    /// Replaces the tag of `image` with its mapping in `tags` or, when
+    /// missing, adds the prefix and suffix to it. The tags of images pinned
+    /// by digest are kept, as they must describe the digest.
+    pub fn apply(&self, image: &mut ImageRef) {
+        let (Some(tag), None) = (&image.tag, &image.hash) else {
+            return;
+        };
+        let retagged = match self.tags.get(tag) {
+            Some(mapped) => mapped.clone(),
+            None => format!(
+                "{}{}{}",
+                self.tag_prefix.as_deref().unwrap_or_default(),
+                tag,
+                self.tag_suffix.as_deref().unwrap_or_default()
+            ),
+        };
+        image.tag = Some(retagged);
+    }
+
+    /// Checks that the tags written into images are valid
+    fn validate(&self) -> Result<(), String> {
+        let valid = |tag: &str| {
+            tag.chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+        };
+        for (from, to) in &self.tags {
+            if to.is_empty() || to.starts_with(['.', '-']) || !valid(to) {
+                return Err(format!("invalid tag {} replacing {}", to, from));
+            }
+        }
+        for affix in self.tag_prefix.iter().chain(&self.tag_suffix) {
+            if !valid(affix) {
+                return Err(format!("invalid tag prefix or suffix {}", affix));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Rule {
+    /// Compiles the regular expression of the rule, if any
+    pub fn regex(&self) -> Option<Result<Regex, String>> {
+        match self {
+            Rule::Prefix { .. } | Rule::Exact { .. } => None,
+            Rule::Regex { pattern, .. } => {
+                Some(Regex::new(pattern).map_err(|e| format!("invalid rule {}: {}", pattern, e)))
+            }
+        }
+    }
+}
+
+impl From<&str> for Destination {
+    fn from(destination: &str) -> Self {
+        Destination::Prefix(destination.to_string())
+    }
+}
+
+/// Default of the settings enabled unless turned off
+fn default_true() -> bool {
+    true
+}
+
+/// Default of the host error policies rejecting the requests
+fn fail_closed() -> HostErrorPolicy {
+    HostErrorPolicy::FailClosed
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            repos: Default::default(),
+            rules: Default::default(),
+            match_strategy: Default::default(),
+            reject_templated_images: Default::default(),
+            unify_k8s_registries: Default::default(),
+            registry_aliases: Default::default(),
+            preset: Default::default(),
+            preset_registry: Default::default(),
+            request_replication: Default::default(),
+            orgs: Default::default(),
+            bypass_annotation: Default::default(),
+            baseline: Default::default(),
+            fail_on_conflicts: Default::default(),
+            excluded_os: Default::default(),
+            debug_images: Default::default(),
+            on_host_error: Default::default(),
+            on_signature_host_error: HostErrorPolicy::FailClosed,
+            default_tag: Default::default(),
+            skip_annotation: Default::default(),
+            record_original_image: Default::default(),
+            record_mutation_summary: Default::default(),
+            inject_original_image: Default::default(),
+            original_image_env_var: Default::default(),
+            emit_warnings: Default::default(),
+            enforce: Default::default(),
+            dry_run: Default::default(),
+            reject_on_no_match: Default::default(),
+            passthrough_registries: Default::default(),
+            default_registry: Default::default(),
+            pull_secrets: Default::default(),
+            pin_digest: Default::default(),
+            verify_destination_exists: Default::default(),
+            on_missing: Default::default(),
+            signatures: Default::default(),
+            reject_invalid_images: Default::default(),
+            strict_image_references: Default::default(),
+            mutate_containers: true,
+            mutate_init_containers: true,
+            mutate_ephemeral_containers: true,
+            container_filters: Default::default(),
+            set_image_pull_policy: Default::default(),
+            exclude_images: Default::default(),
+            image_env_vars: Default::default(),
+            image_args: Default::default(),
+            image_annotations: Default::default(),
+            operations: Default::default(),
+            on_update: Default::default(),
+            skip_digest_references: Default::default(),
+            mappings_artifact: Default::default(),
+            artifact_repos: Default::default(),
+            mappings_config_map: Default::default(),
+            config_map_repos: Default::default(),
+            custom_resources: Default::default(),
+            namespace_overrides: Default::default(),
+            mirror_sets: Default::default(),
+        }
+    }
+}
+
+impl Settings {
+    /// Determines if the requests of the admission `operation`, e.g.
+    /// `CREATE`, are mutated
+    pub fn mutates_operation(&self, operation: &str) -> bool {
+        let operations = self.operations.as_deref().unwrap_or(&[Operation::Create]);
+        operations.iter().any(|op| match op {
+            Operation::Create => operation == "CREATE",
+            Operation::Update => operation == "UPDATE",
+        })
+    }
+
+    /// Returns the annotation, or label, exempting workloads from mutation
+    pub fn skip_annotation(&self) -> String {
+        self.skip_annotation
+            .clone()
+            .unwrap_or_else(|| format!("{}/skip", ANNOTATION_PREFIX))
+    }
+
+    /// Returns the environment variable holding the original image of the
+    /// rewritten containers
+    pub fn original_image_env_var(&self) -> &str {
+        self.original_image_env_var
+            .as_deref()
+            .unwrap_or("KW_ORIGINAL_IMAGE")
+    }
+
+    /// Returns the registry aliases: the Docker Hub ones, overridden by the
+    /// `registry_aliases`
+    pub fn registry_aliases(&self) -> HashMap<String, String> {
+        image::DOCKER_HUB_ALIASES
+            .iter()
+            .map(|alias| (alias.to_string(), "docker.io".to_string()))
+            .chain(self.registry_aliases.clone())
+            .collect()
+    }
+
+    /// Returns a source as matched against the images: without trailing
+    /// `/`, its registry unaliased and, with `unify_k8s_registries`, unified
+    pub fn normalize_source(&self, source: &str, aliases: &HashMap<String, String>) -> String {
+        let source = image::unalias_registry(source.trim_end_matches('/'), aliases);
+        if self.unify_k8s_registries {
+            image::unify_k8s_registry(&source)
+        } else {
+            source
+        }
+    }
+
+    /// Returns the layers of repo mappings, from the lowest to the highest
+    /// precedence
+    fn layers(&self) -> Vec<(Layer, HashMap<String, Destination>)> {
+        let preset = self
+            .preset
+            .as_ref()
+            .and_then(|name| presets::expand(name, self.preset_registry.as_deref()).ok())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(src, dest)| (src, Destination::Prefix(dest)))
+            .collect();
+        vec![
+            (Layer::Preset, preset),
+            (Layer::Baseline, self.baseline.clone()),
+            (Layer::Artifact, self.artifact_repos.clone()),
+            (Layer::Repos, self.repos.clone()),
+            (Layer::ConfigMap, self.config_map_repos.clone()),
+        ]
+    }
+
+    /// Returns the mapping of repos to use, merging every layer so that the
+    /// mappings ConfigMap overrides `repos`, which overrides the mappings
+    /// artifact, which overrides `baseline`, which overrides the preset
+    pub fn effective_repos(&self) -> HashMap<String, Destination> {
+        self.layers()
+            .into_iter()
+            .flat_map(|(_, repos)| repos)
+            .collect()
+    }
+
+    /// Returns every rewrite rule in the form of the rules list, along with
+    /// its identifier, e.g. `repos/docker.io`: the entries of `rules`, in the
+    /// order of the list, followed by the merged repo mappings converted
+    /// into prefix rules, sorted by source
+    pub fn effective_rules(&self) -> Vec<(String, Rule)> {
+        let mut repos: Vec<(String, Destination)> = self.effective_repos().into_iter().collect();
+        repos.sort_by(|(a, _), (b, _)| a.cmp(b));
+        self.rules
+            .iter()
+            .map(|rule| {
+                let id = match rule {
+                    Rule::Prefix { source, .. } => source,
+                    Rule::Regex { pattern, .. } => pattern,
+                    Rule::Exact { exact, .. } => exact,
+                };
+                (format!("rules/{}", id), rule.clone())
+            })
+            .chain(repos.into_iter().map(|(source, destination)| {
+                let id = format!("repos/{}", source);
+                let target = destination.into_target();
+                (id, Rule::Prefix { source, target })
+            }))
+            .collect()
+    }
+
+    /// Determines if any rule is configured, either in a layer of repo
+    /// mappings, in the rules list, as an organization rule, as a debug
+    /// image, as the default registry or in the mappings artifact or
+    /// ConfigMap
+    pub fn has_rules(&self) -> bool {
+        !self.orgs.is_empty()
+            || !self.rules.is_empty()
+            || self.default_registry.is_some()
+            || !self.debug_images.is_empty()
+            || self.mappings_artifact.is_some()
+            || self.mappings_config_map.is_some()
+            || !self.mirror_sets.is_empty()
+            || self.layers().iter().any(|(_, repos)| !repos.is_empty())
+    }
+
+    /// Returns the settings merged with the `repos` and `rules` keys of the
+    /// mappings ConfigMap, holding JSON documents: its repos are the layer of
+    /// the highest precedence, checked for conflicts like the other layers,
+    /// and its rules are appended to the rules list
+    pub fn merge_config_map(&self, data: &BTreeMap<String, String>) -> Result<Settings, String> {
+        self.merge_layer(Layer::ConfigMap, data.get("repos"), data.get("rules"))
+    }
+
+    /// Returns the settings merged with the `repos` and `rules` annotations
+    /// of the manifest of the mappings artifact, holding JSON documents, e.g.
+    /// `mutate-repos-policy.kubewarden.io/repos`: its repos are the layer
+    /// between `baseline` and `repos` and its rules are appended to the
+    /// rules list
+    pub fn merge_artifact(
+        &self,
+        annotations: &BTreeMap<String, String>,
+    ) -> Result<Settings, String> {
+        self.merge_layer(
+            Layer::Artifact,
+            annotations.get(&format!("{}/repos", ANNOTATION_PREFIX)),
+            annotations.get(&format!("{}/rules", ANNOTATION_PREFIX)),
+        )
+    }
+
+    /// Returns the settings with the JSON `repos` as the loaded `layer` and
+    /// the JSON `rules` appended to the rules list, validated
+    fn merge_layer(
+        &self,
+        layer: Layer,
+        repos: Option<&String>,
+        rules: Option<&String>,
+    ) -> Result<Settings, String> {
+        let mut merged = self.clone();
+        if let Some(repos) = repos {
+            let repos: HashMap<String, Destination> =
+                serde_json::from_str(repos).map_err(|e| format!("invalid repos: {}", e))?;
+            match layer {
+                Layer::Artifact => merged.artifact_repos = repos,
+                _ => merged.config_map_repos = repos,
+            }
+        }
+        if let Some(rules) = rules {
+            let rules: Vec<Rule> =
+                serde_json::from_str(rules).map_err(|e| format!("invalid rules: {}", e))?;
+            merged.rules.extend(rules);
+        }
+        kubewarden::settings::Validatable::validate(&merged)?;
+        Ok(merged)
+    }
+
+    /// Returns the settings with the `mappings` overriding the global ones:
+    /// its repos override the entries of the same source and its rules come
+    /// before the rules list
+    pub fn with_mappings(&self, mappings: &Mappings) -> Settings {
+        let mut merged = self.clone();
+        // the overrides take precedence over every layer, the ConfigMap included
+        for src in mappings.repos.keys() {
+            merged.config_map_repos.remove(src);
+        }
+        merged.repos.extend(mappings.repos.clone());
+        merged.rules = mappings.rules.iter().chain(&self.rules).cloned().collect();
+        merged
+    }
+
+    /// Returns the settings applying to the objects of `namespace`, with its
+    /// `namespaceOverrides` entry, if any
+    pub fn for_namespace(&self, namespace: &str) -> Option<Settings> {
+        let mappings = self.namespace_overrides.get(namespace)?;
+        Some(self.with_mappings(mappings))
+    }
+
+    /// Returns the settings applying to the objects selecting the mirror set
+    /// `name` with the mirror annotation, or an error for unknown sets
+    pub fn for_mirror_set(&self, name: &str) -> Result<Settings, String> {
+        match self.mirror_sets.get(name) {
+            Some(mappings) => Ok(self.with_mappings(mappings)),
+            None => {
+                let mut names: Vec<&str> = self.mirror_sets.keys().map(String::as_str).collect();
+                names.sort();
+                Err(format!(
+                    "unknown mirror set {} selected by the {} annotation, expected one of: {}",
+                    name,
+                    mirror_annotation(),
+                    names.join(", ")
+                ))
+            }
+        }
+    }
+
+    /// Returns the `excludeImages` patterns, normalized like the sources
+    pub fn excluded_images(&self) -> Vec<String> {
+        let aliases = self.registry_aliases();
+        self.exclude_images
+            .iter()
+            .map(|pattern| self.normalize_source(pattern, &aliases))
+            .collect()
+    }
+
+    /// Returns the literal prefixes of the destinations of every rule, up to
+    /// their first placeholder or capture group reference, under which the
+    /// images are already mirrored
+    pub fn destination_prefixes(&self) -> Vec<String> {
+        let mut prefixes: Vec<String> = self
+            .effective_rules()
+            .into_iter()
+            .flat_map(|(_, rule)| match rule {
+                Rule::Prefix { target, .. } => {
+                    let mut destinations = vec![target.destination];
+                    destinations.extend(target.failover);
+                    destinations
+                }
+                Rule::Regex { replace, .. } => vec![replace],
+                Rule::Exact { replace, .. } => vec![ImageRef::parse(&replace).repository()],
+            })
+            .chain(self.orgs.values().flat_map(|dest| {
+                let mut destinations = vec![dest.prefix().to_string()];
+                destinations.extend_from_slice(dest.failover());
+                destinations
+            }))
+            .chain(self.default_registry.clone())
+            .filter_map(|dest| literal_prefix(&dest))
+            .collect();
+        prefixes.sort();
+        prefixes.dedup();
+        prefixes
+    }
+
+    /// Returns the registries whose images are admitted without matching any
+    /// rule: the `passthrough_registries` and the registries of every
+    /// destination, so that images already rewritten are admitted again
+    pub fn passthrough_registries(&self) -> Vec<String> {
+        let destinations = self
+            .destination_prefixes()
+            .into_iter()
+            .chain(self.debug_images.values().cloned())
+            .map(|dest| dest.split('/').next().unwrap_or_default().to_string());
+        let mut registries: Vec<String> = self
+            .passthrough_registries
+            .iter()
+            .cloned()
+            .chain(destinations)
+            .collect();
+        registries.sort();
+        registries.dedup();
+        registries
+    }
+
+    /// Returns a description of every destination matched by the source of
+    /// a mapping or rule, whose rewritten images would be rewritten again
+    pub fn chains(&self) -> Vec<String> {
+        let sources: Vec<String> = self
+            .effective_rules()
+            .into_iter()
+            .filter_map(|(_, rule)| match rule {
+                Rule::Prefix { source, .. } => Some(source),
+                Rule::Regex { .. } | Rule::Exact { .. } => None,
+            })
+            .collect();
+        let prefixes = self.destination_prefixes();
+        let mut chains: Vec<String> = prefixes
+            .iter()
+            .flat_map(|prefix| {
+                sources
+                    .iter()
+                    .filter(move |source| image::source_overlaps(source, prefix))
+                    .map(move |source| {
+                        format!(
+                            "destination {} is matched by the source {}, \
+                             images rewritten to it would be rewritten again",
+                            prefix, source
+                        )
+                    })
+            })
+            .collect();
+        // an organization rule is expected to keep its own organization, e.g.
+        // bitnami to registry.corp/bitnami, whose images are left as mirrored
+        for (org, dest) in &self.orgs {
+            let own: Vec<String> = std::iter::once(dest.prefix())
+                .chain(dest.failover().iter().map(String::as_str))
+                .filter_map(literal_prefix)
+                .collect();
+            for prefix in &prefixes {
+                if prefix.split('/').nth(1) == Some(org.as_str()) && !own.contains(prefix) {
+                    chains.push(format!(
+                        "destination {} is matched by the organization {}, \
+                         images rewritten to it would be rewritten again",
+                        prefix, org
+                    ));
+                }
+            }
+        }
+        chains.sort();
+        chains.dedup();
+        chains
+    }
+
+    /// Returns a description of every source written several times, once
+    /// normalized, in `repos` or in the rules list, all but one of them
+    /// never being applied
+    pub fn duplicates(&self) -> Vec<String> {
+        let aliases = self.registry_aliases();
+        let normalize = |src: &str| self.normalize_source(src, &aliases);
+        let mut duplicates = Vec::new();
+
+        let mut repos: Vec<&String> = self.repos.keys().collect();
+        repos.sort();
+        let mut seen: HashMap<String, &String> = HashMap::new();
+        for src in repos {
+            if let Some(previous) = seen.insert(normalize(src), src) {
+                duplicates.push(format!(
+                    "repos sources {} and {} are the same, keep one of them",
+                    previous, src
+                ));
+            }
+        }
+
+        let mut exact_rules: Vec<(String, &Conditions)> = Vec::new();
+        for (i, rule) in self.rules.iter().enumerate() {
+            let Rule::Exact {
+                exact, conditions, ..
+            } = rule
+            else {
+                continue;
+            };
+            let normalized = ImageRef::parse(&normalize(exact)).to_string();
+            if let Some(previous) = exact_rules.iter().position(|(prev, prev_conditions)| {
+                *prev == normalized && *prev_conditions == conditions
+            }) {
+                duplicates.push(format!(
+                    "rule #{} for {} is shadowed by rule #{} with the same image and \
+                     conditions, remove it",
+                    i + 1,
+                    exact,
+                    previous + 1
+                ));
+            }
+            exact_rules.push((normalized, conditions));
+        }
+
+        let mut rules: Vec<(String, &Conditions)> = Vec::new();
+        for (i, rule) in self.rules.iter().enumerate() {
+            let Rule::Prefix { source, target } = rule else {
+                continue;
+            };
+            let normalized = normalize(source);
+            if let Some(previous) = rules.iter().position(|(src, conditions)| {
+                *src == normalized && **conditions == target.conditions
+            }) {
+                duplicates.push(format!(
+                    "rule #{} for {} is shadowed by rule #{} with the same source and \
+                     conditions, remove it",
+                    i + 1,
+                    source,
+                    previous + 1
+                ));
+            }
+            if target.conditions == Conditions::default() {
+                if let Some(src) = self.repos.keys().find(|src| normalize(src) == normalized) {
+                    duplicates.push(format!(
+                        "repos source {} is shadowed by rule #{} for {}, remove one of them",
+                        src,
+                        i + 1,
+                        source
+                    ));
+                }
+            }
+            rules.push((normalized, &target.conditions));
+        }
+        duplicates
+    }
+
+    /// Returns a description of every source mapped to different
+    /// destinations by different layers
+    pub fn conflicts(&self) -> Vec<String> {
+        let mut seen: HashMap<String, (Layer, Destination)> = HashMap::new();
+        let mut conflicts = Vec::new();
+        for (layer, repos) in self.layers() {
+            for (src, dest) in repos {
+                if let Some((prev_layer, prev_dest)) = seen.get(&src) {
+                    if *prev_dest != dest {
+                        conflicts.push(format!(
+                            "{} is mapped to {} by {} and to {} by {}",
+                            src,
+                            prev_dest.prefix(),
+                            prev_layer,
+                            dest.prefix(),
+                            layer
+                        ));
+                    }
+                }
+                seen.insert(src, (layer, dest));
+            }
+        }
+        conflicts.sort();
+        conflicts
+    }
+}
+
+/// Checks that a source, or the literal prefix of a destination, is a
+/// registry optionally followed by components of the repository path, e.g.
+/// `docker.io/bitnami`, without tag nor digest. Only sources can be globs.
+fn validate_prefix(prefix: &str, destination: bool) -> Result<(), String> {
+    let kind = if destination { "destination" } else { "source" };
+    let components: Vec<&str> = prefix.trim_end_matches('/').split('/').collect();
+    if prefix.is_empty() {
+        return Err(format!("empty {}", kind));
+    }
+    if prefix.contains(char::is_whitespace) || components.iter().any(|c| c.is_empty()) {
+        return Err(format!(
+            "{} {:?} must be made of non-empty components without spaces",
+            kind, prefix
+        ));
+    }
+    if destination && prefix.contains('*') {
+        return Err(format!("destination {} cannot be a glob", prefix));
+    }
+    let registry = components[0];
+    if !(registry.contains(['.', ':', '*']) || registry == "localhost") {
+        return Err(format!(
+            "{} {} must start with a registry, e.g. docker.io/{}",
+            kind, prefix, prefix
+        ));
+    }
+    if prefix.contains('@') || components[1..].iter().any(|c| c.contains(':')) {
+        return Err(format!(
+            "{} {} must be a registry or repository prefix, without tag nor digest",
+            kind, prefix
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that `reference` is a fully-qualified image reference, with a
+/// registry and a tag or digest
+fn validate_reference(reference: &str) -> Result<(), String> {
+    ImageRef::try_parse(reference).map_err(|e| format!("{} in {}", e, reference))?;
+    if !image::has_registry(reference) || !image::has_explicit_tag(reference) {
+        return Err(format!(
+            "{} must be a fully-qualified reference, with a registry and a tag \
+             or digest, e.g. registry.k8s.io/pause:3.9",
+            reference
+        ));
+    }
+    Ok(())
+}
+
+/// Checks the placeholders of a destination, and its literal prefix
+fn validate_destination(destination: &str) -> Result<(), String> {
+    if destination.is_empty() {
+        return Err("empty destination".to_string());
+    }
+    template::validate(destination)?;
+    match literal_prefix(destination) {
+        Some(prefix) if prefix == destination.trim_end_matches('/') => {
+            validate_prefix(&prefix, true)
+        }
+        // the components following a placeholder may hold a templated tag
+        Some(prefix) => validate_prefix(&prefix, true)
+            .map_err(|e| format!("{} of templated destination {}", e, destination)),
+        None => Ok(()),
+    }
+}
+
+/// Returns the literal prefix of a destination, up to its first placeholder
+/// or capture group reference, trimmed to whole components
+fn literal_prefix(destination: &str) -> Option<String> {
+    let end = destination.find(['{', '$']).unwrap_or(destination.len());
+    let mut prefix = &destination[..end];
+    if end < destination.len() && !prefix.ends_with('/') {
+        prefix = prefix.rsplit_once('/').map_or("", |(prefix, _)| prefix);
+    }
+    let prefix = prefix.trim_end_matches('/');
+    (!prefix.is_empty()).then(|| prefix.to_string())
 }
 
 impl kubewarden::settings::Validatable for Settings {
     fn validate(&self) -> Result<(), String> {
         info!(LOG_DRAIN, "starting settings validation");
-        if self.repos.is_empty() {
+        if let Some(preset) = &self.preset {
+            presets::expand(preset, self.preset_registry.as_deref())?;
+        } else if self.repos.is_empty() && self.rules.is_empty() {
             info!(LOG_DRAIN, "mapping of repos is empty, skipping");
         }
+        for rule in &self.rules {
+            match rule {
+                Rule::Prefix { source, target } => {
+                    validate_prefix(source, false)
+                        .map_err(|e| format!("rule to {}: {}", target.destination, e))?;
+                    target.validate_destination()?;
+                    target.conditions.validate()?;
+                    target.tag_rewrite.validate()?;
+                }
+                Rule::Regex {
+                    conditions,
+                    tag_rewrite,
+                    ..
+                } => {
+                    rule.regex().transpose()?;
+                    conditions.validate()?;
+                    tag_rewrite.validate()?;
+                }
+                Rule::Exact {
+                    exact,
+                    replace,
+                    conditions,
+                    ..
+                } => {
+                    validate_reference(exact)
+                        .and_then(|_| validate_reference(replace))
+                        .map_err(|e| format!("rule for {}: {}", exact, e))?;
+                    conditions.validate()?;
+                }
+            }
+        }
+        for (src, dest) in self.effective_repos() {
+            validate_prefix(&src, false).map_err(|e| format!("repos: {}", e))?;
+            match &dest {
+                Destination::Prefix(prefix) => validate_destination(prefix),
+                Destination::Conditional(target) => target.validate_destination(),
+            }
+            .map_err(|e| format!("repos source {}: {}", src, e))?;
+            if let Destination::Conditional(target) = dest {
+                target.conditions.validate()?;
+                target.tag_rewrite.validate()?;
+            }
+        }
+        for (org, dest) in &self.orgs {
+            if org.is_empty() || org.contains('/') {
+                return Err(format!(
+                    "orgs: organization {:?} must be a single component of the \
+                     repository path, e.g. bitnami",
+                    org
+                ));
+            }
+            match dest {
+                Destination::Prefix(prefix) => validate_destination(prefix),
+                Destination::Conditional(target) => target.validate_destination(),
+            }
+            .map_err(|e| format!("orgs {}: {}", org, e))?;
+            if let Destination::Conditional(target) = dest {
+                target.conditions.validate()?;
+                target.tag_rewrite.validate()?;
+            }
+        }
+        for (src, dest) in &self.debug_images {
+            ImageRef::try_parse(src).map_err(|e| format!("debugImages: {} in {}", e, src))?;
+            validate_reference(dest).map_err(|e| format!("debugImages {}: {}", src, e))?;
+        }
+        for (alias, registry) in &self.registry_aliases {
+            if alias.is_empty()
+                || alias.contains('/')
+                || registry.is_empty()
+                || registry.contains('/')
+            {
+                return Err(format!(
+                    "registryAliases: {:?} and {:?} must both be registries, e.g. \
+                     index.docker.io: docker.io",
+                    alias, registry
+                ));
+            }
+        }
+        if let Some(registry) = &self.default_registry {
+            validate_destination(registry).map_err(|e| format!("defaultRegistry: {}", e))?;
+        }
+        for pattern in &self.exclude_images {
+            let registry = pattern.split('/').next().unwrap_or_default();
+            if image::is_whole_reference(pattern) {
+                ImageRef::try_parse(pattern)
+                    .map_err(|e| e.to_string())
+                    .and_then(|_| validate_prefix(registry, false))
+            } else if !registry.contains('*') && !image::is_valid_registry(registry) {
+                Err(format!("invalid registry {}", registry))
+            } else {
+                validate_prefix(pattern, false)
+            }
+            .map_err(|e| format!("excludeImages {}: {}", pattern, e))?;
+        }
+        if let Some(policy) = &self.set_image_pull_policy {
+            if !PULL_POLICIES.contains(&policy.as_str()) {
+                return Err(format!(
+                    "setImagePullPolicy: unknown pull policy {}, expected one of {}",
+                    policy,
+                    PULL_POLICIES.join(", ")
+                ));
+            }
+        }
+        let duplicates = self.duplicates();
+        if !duplicates.is_empty() {
+            return Err(format!("duplicate sources: {}", duplicates.join("; ")));
+        }
+        if let Some(artifact) = &self.mappings_artifact {
+            validate_reference(artifact).map_err(|e| format!("mappingsArtifact: {}", e))?;
+        }
+        if let Some(config_map) = &self.mappings_config_map {
+            if config_map.namespace.is_empty() || config_map.name.is_empty() {
+                return Err(format!(
+                    "mappings ConfigMap {} needs a namespace and a name",
+                    config_map
+                ));
+            }
+        }
+        for (kind, paths) in &self.custom_resources {
+            if !kind.contains('/') {
+                return Err(format!(
+                    "custom resource {} must be written as group/version/Kind",
+                    kind
+                ));
+            }
+            for path in paths {
+                jsonpath::parse(path)?;
+            }
+        }
+        let validate_mappings = |mappings: &Mappings| {
+            let mut merged = self.with_mappings(mappings);
+            merged.namespace_overrides.clear();
+            merged.mirror_sets.clear();
+            kubewarden::settings::Validatable::validate(&merged)
+        };
+        for (namespace, mappings) in &self.namespace_overrides {
+            validate_mappings(mappings)
+                .map_err(|e| format!("namespaceOverrides {}: {}", namespace, e))?;
+        }
+        for (name, mappings) in &self.mirror_sets {
+            validate_mappings(mappings).map_err(|e| format!("mirrorSets {}: {}", name, e))?;
+        }
+        let chains = self.chains();
+        // with the all strategy, chaining rules is the point
+        if !chains.is_empty() && self.match_strategy != MatchStrategy::All {
+            return Err(format!("chained rules: {}", chains.join("; ")));
+        }
+        let conflicts = self.conflicts();
+        if !conflicts.is_empty() {
+            if self.fail_on_conflicts {
+                return Err(format!("conflicting repos: {}", conflicts.join("; ")));
+            }
+            for conflict in conflicts {
+                info!(LOG_DRAIN, "overridden repo"; "conflict" => conflict);
+            }
+        }
         Ok(())
     }
 }
@@ -33,8 +1501,879 @@ mod tests {
     fn validate_settings() -> Result<(), ()> {
         let settings = Settings {
             repos: HashMap::new(),
+            ..Default::default()
+        };
+        assert!(settings.validate().is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn container_toggles_default_to_true() -> Result<(), ()> {
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "mutateInitContainers": false
+        }))
+        .unwrap();
+        assert!(settings.mutate_containers);
+        assert!(!settings.mutate_init_containers);
+        assert!(settings.mutate_ephemeral_containers);
+        assert!(Settings::default().mutate_init_containers);
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_preset() -> Result<(), ()> {
+        let mut settings = Settings {
+            preset: Some("harbor".to_string()),
+            ..Default::default()
+        };
+        assert!(settings.validate().is_err());
+
+        settings.preset_registry = Some("harbor.example.com".to_string());
+        assert!(settings.validate().is_ok());
+
+        settings.preset = Some("unknown".to_string());
+        assert!(settings.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn effective_repos_overrides_preset() -> Result<(), ()> {
+        let settings = Settings {
+            repos: HashMap::from([("quay.io".to_string(), "quay.example.com".into())]),
+            preset: Some("tencentcloud".to_string()),
+            ..Default::default()
+        };
+        let repos = settings.effective_repos();
+        assert_eq!(repos["quay.io"].prefix(), "quay.example.com");
+        assert_eq!(repos["docker.io"].prefix(), "dockerhub.tencentcloudcr.com");
+        Ok(())
+    }
+
+    #[test]
+    fn repos_are_converted_into_rules() -> Result<(), ()> {
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "repos": {
+                "quay.io": "quay.example.com",
+                "docker.io": {"destination": "mirror.corp/docker", "os": "linux"}
+            },
+            "baseline": {"gcr.io": "gcr.example.com"},
+            "rules": [{"match": "^ghcr\\.io/(.*)$", "replace": "mirror.corp/ghcr/$1"}]
+        }))
+        .unwrap();
+
+        let rules = settings.effective_rules();
+        let ids: Vec<&str> = rules.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(
+            ids,
+            vec![
+                "rules/^ghcr\\.io/(.*)$",
+                "repos/docker.io",
+                "repos/gcr.io",
+                "repos/quay.io"
+            ]
+        );
+        let Rule::Prefix { source, target } = &rules[1].1 else {
+            panic!("repos must be converted into prefix rules");
+        };
+        assert_eq!(source, "docker.io");
+        assert_eq!(target.destination, "mirror.corp/docker");
+        assert_eq!(target.conditions.os.as_deref(), Some("linux"));
+        assert!(matches!(
+            &rules[3].1,
+            Rule::Prefix { target, .. } if target.destination == "quay.example.com"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn destination_conditions() -> Result<(), ()> {
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "repos": {
+                "docker.io": "dockerhub.example.com",
+                "docker.io/library": {
+                    "destination": "official.example.com",
+                    "officialOnly": true
+                },
+                "gcr.io": {
+                    "destination": "gcr.example.com/flat",
+                    "pathDepth": 1
+                }
+            }
+        }))
+        .unwrap();
+
+        let official = &settings.repos["docker.io/library"];
+        assert_eq!(official.prefix(), "official.example.com");
+        assert!(official.applies_to(&ImageRef::parse("alpine"), "linux", None));
+        assert!(!official.applies_to(&ImageRef::parse("bitnami/nginx"), "linux", None));
+
+        let flat = &settings.repos["gcr.io"];
+        assert!(flat.applies_to(&ImageRef::parse("gcr.io/etcd"), "linux", None));
+        assert!(!flat.applies_to(&ImageRef::parse("gcr.io/google/etcd"), "linux", None));
+
+        assert!(settings.repos["docker.io"].applies_to(
+            &ImageRef::parse("bitnami/nginx"),
+            "linux",
+            None
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn merge_layers_with_precedence() -> Result<(), ()> {
+        let mut settings = Settings {
+            preset: Some("tencentcloud".to_string()),
+            baseline: HashMap::from([
+                (
+                    "docker.io".to_string(),
+                    "baseline.example.com/docker".into(),
+                ),
+                ("ghcr.io".to_string(), "baseline.example.com/ghcr".into()),
+            ]),
+            repos: HashMap::from([
+                ("ghcr.io".to_string(), "local.example.com/ghcr".into()),
+                ("quay.io".to_string(), "quay.tencentcloudcr.com".into()),
+            ]),
+            ..Default::default()
+        };
+
+        let repos = settings.effective_repos();
+        assert_eq!(repos["gcr.io"].prefix(), "gcr.tencentcloudcr.com");
+        assert_eq!(repos["docker.io"].prefix(), "baseline.example.com/docker");
+        assert_eq!(repos["ghcr.io"].prefix(), "local.example.com/ghcr");
+        assert_eq!(repos["quay.io"].prefix(), "quay.tencentcloudcr.com");
+
+        // quay.io is mapped to the same destination by the preset and repos
+        assert_eq!(
+            settings.conflicts(),
+            vec![
+                "docker.io is mapped to dockerhub.tencentcloudcr.com by preset and to \
+                 baseline.example.com/docker by baseline",
+                "ghcr.io is mapped to baseline.example.com/ghcr by baseline and to \
+                 local.example.com/ghcr by repos",
+            ]
+        );
+        assert!(settings.validate().is_ok());
+
+        settings.fail_on_conflicts = true;
+        assert!(settings.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn has_rules() -> Result<(), ()> {
+        assert!(!Settings::default().has_rules());
+        assert!(Settings {
+            preset: Some("daocloud".to_string()),
+            ..Default::default()
+        }
+        .has_rules());
+        assert!(Settings {
+            orgs: HashMap::from([("bitnami".to_string(), "registry.corp/bitnami".into())]),
+            ..Default::default()
+        }
+        .has_rules());
+        Ok(())
+    }
+
+    #[test]
+    fn destination_attribution() -> Result<(), ()> {
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "repos": {
+                "docker.io": "dockerhub.example.com",
+                "quay.io": {
+                    "destination": "quay.example.com",
+                    "team": "platform",
+                    "costCenter": "cc-42"
+                }
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(
+            settings.repos["quay.io"].attribution(),
+            Attribution {
+                team: Some("platform".to_string()),
+                cost_center: Some("cc-42".to_string()),
+            }
+        );
+        assert_eq!(
+            settings.repos["docker.io"].attribution(),
+            Attribution::default()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn validate_rules() -> Result<(), ()> {
+        let mut settings: Settings = serde_json::from_value(serde_json::json!({
+            "rules": [
+                {"source": "quay.io", "destination": "mirror.corp/quay"},
+                {"match": "^ghcr\\.io/(.*)$", "replace": "mirror.corp/ghcr/$1", "os": "linux"}
+            ]
+        }))
+        .unwrap();
+        assert!(matches!(&settings.rules[1], Rule::Regex { conditions, .. }
+            if conditions.os.as_deref() == Some("linux")));
+        assert!(settings.validate().is_ok());
+
+        settings.rules.push(Rule::Regex {
+            pattern: "^ghcr\\.io/(.*$".to_string(),
+            replace: "mirror.corp/ghcr/$1".to_string(),
+            conditions: Conditions::default(),
+            attribution: Attribution::default(),
+            tag_rewrite: TagRewrite::default(),
+            priority: 0,
+        });
+        assert!(settings.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn validate_templated_destinations() -> Result<(), ()> {
+        let mut settings = Settings {
+            repos: HashMap::from([(
+                "docker.io".to_string(),
+                "harbor.corp/{registry}/{image}:{tag}".into(),
+            )]),
+            ..Default::default()
         };
         assert!(settings.validate().is_ok());
+
+        settings
+            .repos
+            .insert("quay.io".to_string(), "harbor.corp/{repo}".into());
+        assert!(settings.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn passthrough_registries() -> Result<(), ()> {
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "repos": {"docker.io": "dockerhub.example.com"},
+            "rules": [{"match": "^quay\\.io/(.*)$", "replace": "mirror.corp:5000/quay/$1"}],
+            "orgs": {"bitnami": "registry.corp/bitnami"},
+            "passthroughRegistries": ["internal.corp"]
+        }))
+        .unwrap();
+        assert_eq!(
+            settings.passthrough_registries(),
+            vec![
+                "dockerhub.example.com",
+                "internal.corp",
+                "mirror.corp:5000",
+                "registry.corp"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn signatures() -> Result<(), ()> {
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "signatures": [
+                {"image": "mirror.corp/*", "pubKeys": ["KEY"], "annotations": {"env": "prod"}},
+                {"image": "mirror.corp/tools/*", "keyless": [{"issuer": "https://accounts.example.com", "subject": "ci@example.com"}]},
+                {"image": "mirror.corp/kw/*", "keylessPrefix": [{"issuer": "https://token.actions.githubusercontent.com", "urlPrefix": "https://github.com/kubewarden"}]},
+                {"image": "mirror.corp/gh/*", "githubActions": {"owner": "kubewarden"}}
+            ]
+        }))
+        .unwrap();
+
+        assert!(
+            matches!(&settings.signatures[0], Signature::PubKeys { annotations: Some(a), .. } if a["env"] == "prod")
+        );
+        assert!(matches!(&settings.signatures[1], Signature::Keyless { .. }));
+        assert!(matches!(
+            &settings.signatures[2],
+            Signature::KeylessPrefix { .. }
+        ));
+        assert!(
+            matches!(&settings.signatures[3], Signature::GithubActions { github_actions, .. } if github_actions.repo.is_none())
+        );
+        assert_eq!(settings.signatures[3].image(), "mirror.corp/gh/*");
+        Ok(())
+    }
+
+    #[test]
+    fn merge_config_map() -> Result<(), ()> {
+        let settings = Settings {
+            repos: HashMap::from([
+                ("docker.io".to_string(), "mirror.corp/docker".into()),
+                ("quay.io".to_string(), "mirror.corp/quay".into()),
+            ]),
+            ..Default::default()
+        };
+
+        let merged = settings
+            .merge_config_map(&BTreeMap::from([(
+                "repos".to_string(),
+                r#"{"quay.io": "harbor.corp/quay"}"#.to_string(),
+            )]))
+            .unwrap();
+        let repos = merged.effective_repos();
+        assert_eq!(repos["docker.io"].prefix(), "mirror.corp/docker");
+        assert_eq!(repos["quay.io"].prefix(), "harbor.corp/quay");
+        assert_eq!(
+            merged.conflicts(),
+            vec![
+                "quay.io is mapped to mirror.corp/quay by repos and to harbor.corp/quay by \
+                  mappingsConfigMap"
+            ]
+        );
+
+        // namespace overrides and mirror sets still take precedence
+        let mappings: Mappings =
+            serde_json::from_value(serde_json::json!({"repos": {"quay.io": "team.corp/quay"}}))
+                .unwrap();
+        let overridden = merged.with_mappings(&mappings).effective_repos();
+        assert_eq!(overridden["quay.io"].prefix(), "team.corp/quay");
+
+        let strict = Settings {
+            fail_on_conflicts: true,
+            ..settings.clone()
+        };
+        assert!(strict
+            .merge_config_map(&BTreeMap::from([(
+                "repos".to_string(),
+                r#"{"quay.io": "harbor.corp/quay"}"#.to_string(),
+            )]))
+            .unwrap_err()
+            .starts_with("conflicting repos: quay.io is mapped"));
+
+        assert!(settings
+            .merge_config_map(&BTreeMap::from([(
+                "rules".to_string(),
+                r#"[{"source": "", "destination": "mirror.corp"}]"#.to_string(),
+            )]))
+            .is_err());
+        assert!(settings
+            .merge_config_map(&BTreeMap::from([("repos".to_string(), "{".to_string())]))
+            .unwrap_err()
+            .starts_with("invalid repos"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_artifact() -> Result<(), ()> {
+        let settings = Settings {
+            baseline: HashMap::from([("quay.io".to_string(), "mirror.corp/quay".into())]),
+            repos: HashMap::from([("docker.io".to_string(), "mirror.corp/docker".into())]),
+            ..Default::default()
+        };
+        let annotations = BTreeMap::from([(
+            "mutate-repos-policy.kubewarden.io/repos".to_string(),
+            r#"{"docker.io": "org.corp/docker", "quay.io": "org.corp/quay"}"#.to_string(),
+        )]);
+
+        let merged = settings.merge_artifact(&annotations).unwrap();
+        let repos = merged.effective_repos();
+        // the artifact overrides the baseline, and is overridden by repos
+        assert_eq!(repos["quay.io"].prefix(), "org.corp/quay");
+        assert_eq!(repos["docker.io"].prefix(), "mirror.corp/docker");
+        assert_eq!(
+            merged.conflicts(),
+            vec![
+                "docker.io is mapped to org.corp/docker by mappingsArtifact and to \
+                  mirror.corp/docker by repos",
+                "quay.io is mapped to mirror.corp/quay by baseline and to org.corp/quay by \
+                  mappingsArtifact"
+            ]
+        );
+
+        let merged = merged
+            .merge_config_map(&BTreeMap::from([(
+                "repos".to_string(),
+                r#"{"quay.io": "local.corp/quay"}"#.to_string(),
+            )]))
+            .unwrap();
+        assert_eq!(
+            merged.effective_repos()["quay.io"].prefix(),
+            "local.corp/quay"
+        );
+
+        let settings = Settings {
+            mappings_artifact: Some("registry.corp/mirrors".to_string()),
+            ..Default::default()
+        };
+        assert!(settings
+            .validate()
+            .unwrap_err()
+            .starts_with("mappingsArtifact: registry.corp/mirrors must be a fully-qualified"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_custom_resources() -> Result<(), ()> {
+        let mut settings: Settings = serde_json::from_value(serde_json::json!({
+            "customResources": {
+                "monitoring.coreos.com/v1/Prometheus": [".spec.image"],
+                "argoproj.io/v1alpha1/Workflow": [".spec.templates[*].container.image"]
+            }
+        }))
+        .unwrap();
+        assert!(settings.validate().is_ok());
+
+        settings
+            .custom_resources
+            .insert("Kafka".to_string(), vec![".spec.kafka.image".to_string()]);
+        assert!(settings.validate().is_err());
+        settings.custom_resources.remove("Kafka");
+
+        settings.custom_resources.insert(
+            "kafka.strimzi.io/v1beta2/Kafka".to_string(),
+            vec!["spec.kafka.image".to_string()],
+        );
+        assert!(settings.validate().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn tag_rewrite() -> Result<(), ()> {
+        let tag_rewrite = TagRewrite {
+            tags: HashMap::from([("latest".to_string(), "1.25.3".to_string())]),
+            tag_suffix: Some("-mirror".to_string()),
+            ..Default::default()
+        };
+        let retag = |image: &str| {
+            let mut image_ref = ImageRef::parse(image);
+            tag_rewrite.apply(&mut image_ref);
+            image_ref.to_string()
+        };
+
+        assert_eq!(retag("nginx"), "docker.io/library/nginx:1.25.3");
+        assert_eq!(retag("nginx:1.24"), "docker.io/library/nginx:1.24-mirror");
+        assert_eq!(
+            retag("nginx@sha256:1234"),
+            "docker.io/library/nginx@sha256:1234"
+        );
+
+        let mut settings: Settings = serde_json::from_value(serde_json::json!({
+            "rules": [{"source": "docker.io", "destination": "mirror.corp", "tags": {"latest": ":1"}}]
+        }))
+        .unwrap();
+        assert!(settings.validate().is_err());
+        settings.rules = vec![serde_json::from_value(serde_json::json!({
+            "match": "^docker\\.io/(.*)$", "replace": "mirror.corp/$1", "tagPrefix": "mirror/"
+        }))
+        .unwrap()];
+        assert!(settings.validate().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn tag_pattern() -> Result<(), ()> {
+        let conditions = Conditions {
+            tag_pattern: Some("v1\\..*".to_string()),
+            ..Default::default()
+        };
+        let regex = conditions.tag_regex().unwrap().unwrap();
+        let hold = |image: &str| conditions.hold(&ImageRef::parse(image), "linux", Some(&regex));
+
+        assert!(hold("ghcr.io/foo/bar:v1.2"));
+        assert!(!hold("ghcr.io/foo/bar:v10.2"));
+        assert!(!hold("ghcr.io/foo/bar:latest"));
+        assert!(!hold("ghcr.io/foo/bar@sha256:1234"));
+
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "rules": [{"source": "ghcr.io", "destination": "mirror.corp", "tagPattern": "v1\\.(.*"}]
+        }))
+        .unwrap();
+        assert!(settings.validate().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn chained_rules() -> Result<(), ()> {
+        let mut settings: Settings = serde_json::from_value(serde_json::json!({
+            "repos": {
+                "quay.io": "mirror.corp/quay.io",
+                "mirror.corp": "backup.corp"
+            }
+        }))
+        .unwrap();
+        assert_eq!(
+            settings.validate(),
+            Err(String::from(
+                "chained rules: destination mirror.corp/quay.io is matched by the \
+                 source mirror.corp, images rewritten to it would be rewritten again"
+            ))
+        );
+
+        settings.repos.remove("mirror.corp");
+        settings.rules = vec![serde_json::from_value(serde_json::json!({
+            "source": "docker.io", "destination": "docker.io/mirror"
+        }))
+        .unwrap()];
+        assert!(settings.validate().is_err());
+
+        settings.rules = vec![serde_json::from_value(serde_json::json!({
+            "source": "ghcr.io", "destination": "harbor.corp/{registry}/{image}"
+        }))
+        .unwrap()];
+        assert!(settings.validate().is_ok());
+        assert_eq!(
+            settings.destination_prefixes(),
+            vec!["harbor.corp", "mirror.corp/quay.io"]
+        );
+
+        // organization rules keeping their own organization are not chains
+        settings.orgs = HashMap::from([
+            ("bitnami".to_string(), "registry.corp/bitnami".into()),
+            ("charts".to_string(), "registry.corp/charts".into()),
+        ]);
+        assert!(settings.validate().is_ok());
+        settings
+            .orgs
+            .insert("vendor".to_string(), "mirror.corp/bitnami".into());
+        assert_eq!(
+            settings.validate(),
+            Err(String::from(
+                "chained rules: destination mirror.corp/bitnami is matched by the \
+                 organization bitnami, images rewritten to it would be rewritten again"
+            ))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_sources_and_destinations() -> Result<(), ()> {
+        let validate = |settings: serde_json::Value| {
+            serde_json::from_value::<Settings>(settings)
+                .unwrap()
+                .validate()
+        };
+
+        assert!(validate(serde_json::json!({
+            "repos": {
+                "docker.io/library": "mirror.corp/library",
+                "*.gcr.io": "mirror.corp/gcr",
+                "localhost:5000": "mirror.corp:5000/local",
+                "quay.io": "harbor.corp/{registry}/{image}:{tag}"
+            },
+            "orgs": {"bitnami": "registry.corp/bitnami"}
+        }))
+        .is_ok());
+
+        assert_eq!(
+            validate(serde_json::json!({"repos": {"bitnami": "mirror.corp/bitnami"}})),
+            Err(String::from(
+                "repos: source bitnami must start with a registry, e.g. docker.io/bitnami"
+            ))
+        );
+        assert_eq!(
+            validate(serde_json::json!({"repos": {"quay.io": ""}})),
+            Err(String::from("repos source quay.io: empty destination"))
+        );
+        assert_eq!(
+            validate(serde_json::json!({"repos": {"quay.io": "mirror.corp/quay:v1"}})),
+            Err(String::from(
+                "repos source quay.io: destination mirror.corp/quay:v1 must be a \
+                 registry or repository prefix, without tag nor digest"
+            ))
+        );
+        assert!(validate(serde_json::json!({"repos": {"quay.io//foo": "mirror.corp"}})).is_err());
+        assert!(validate(serde_json::json!({"repos": {"quay.io": "mirror.corp/*"}})).is_err());
+        assert!(validate(serde_json::json!({"orgs": {"": "registry.corp"}})).is_err());
+        assert!(
+            validate(serde_json::json!({"defaultRegistry": "registry.corp@sha256:1234"})).is_err()
+        );
+
+        assert_eq!(
+            validate(serde_json::json!({
+                "repos": {"quay.io": "mirror.corp/a", "quay.io/": "mirror.corp/b"}
+            })),
+            Err(String::from(
+                "duplicate sources: repos sources quay.io and quay.io/ are the same, \
+                 keep one of them"
+            ))
+        );
+        assert_eq!(
+            validate(serde_json::json!({
+                "unifyK8sRegistries": true,
+                "rules": [
+                    {"source": "registry.k8s.io", "destination": "mirror.corp/k8s"},
+                    {"source": "ghcr.io", "destination": "mirror.corp/ghcr", "os": "windows"},
+                    {"source": "k8s.gcr.io", "destination": "mirror.corp/legacy"}
+                ]
+            })),
+            Err(String::from(
+                "duplicate sources: rule #3 for k8s.gcr.io is shadowed by rule #1 \
+                 with the same source and conditions, remove it"
+            ))
+        );
+        assert_eq!(
+            validate(serde_json::json!({
+                "repos": {"ghcr.io": "mirror.corp/repos"},
+                "rules": [{"source": "ghcr.io", "destination": "mirror.corp/rules"}]
+            })),
+            Err(String::from(
+                "duplicate sources: repos source ghcr.io is shadowed by rule #1 for \
+                 ghcr.io, remove one of them"
+            ))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn container_filters() -> Result<(), ()> {
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "containerFilters": {
+                "containers": {"exclude": ["istio-proxy", "linkerd-*"]},
+                "initContainers": {"include": ["app-*"], "exclude": ["app-debug"]}
+            }
+        }))
+        .unwrap();
+        let filters = &settings.container_filters;
+
+        assert!(filters.containers.allows("app"));
+        assert!(!filters.containers.allows("istio-proxy"));
+        assert!(!filters.containers.allows("linkerd-proxy"));
+        assert!(filters.init_containers.allows("app-migrate"));
+        assert!(!filters.init_containers.allows("app-debug"));
+        assert!(!filters.init_containers.allows("istio-init"));
+        assert!(filters.ephemeral_containers.allows("istio-proxy"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn operations() -> Result<(), ()> {
+        let settings = Settings::default();
+        assert!(settings.mutates_operation("CREATE"));
+        assert!(!settings.mutates_operation("UPDATE"));
+
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "operations": ["CREATE", "UPDATE"],
+            "onUpdate": "imagesChanged"
+        }))
+        .unwrap();
+        assert!(settings.mutates_operation("UPDATE"));
+        assert!(!settings.mutates_operation("DELETE"));
+        assert_eq!(settings.on_update, UpdatePolicy::ImagesChanged);
+
+        assert!(serde_json::from_value::<Settings>(serde_json::json!({
+            "operations": ["DELETE"]
+        }))
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn strip_registry_action() -> Result<(), ()> {
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "repos": {"gcr.io": {"action": "stripRegistry"}},
+            "rules": [{"source": "quay.io", "action": "stripRegistry"}]
+        }))
+        .unwrap();
+        assert!(settings.validate().is_ok());
+        assert_eq!(settings.repos["gcr.io"].action(), Action::StripRegistry);
+        assert!(settings.destination_prefixes().is_empty());
+
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "repos": {"gcr.io": {"destination": "mirror.corp", "action": "stripRegistry"}}
+        }))
+        .unwrap();
+        assert!(settings.validate().is_err());
+
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "rules": [{"source": "quay.io"}]
+        }))
+        .unwrap();
+        assert!(settings.validate().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_image_pull_policy() -> Result<(), ()> {
+        let mut settings = Settings {
+            set_image_pull_policy: Some("IfNotPresent".to_string()),
+            ..Default::default()
+        };
+        assert!(settings.validate().is_ok());
+
+        settings.set_image_pull_policy = Some("ifnotpresent".to_string());
+        assert_eq!(
+            settings.validate(),
+            Err(String::from(
+                "setImagePullPolicy: unknown pull policy ifnotpresent, \
+                 expected one of Always, IfNotPresent, Never"
+            ))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_exclude_images() -> Result<(), ()> {
+        let mut settings = Settings {
+            exclude_images: vec![
+                "registry.k8s.io/pause:3.9".to_string(),
+                "registry.k8s.io/sig-storage".to_string(),
+                "ghcr.io/kubewarden/*".to_string(),
+                "localhost:5000".to_string(),
+            ],
+            ..Default::default()
+        };
+        assert!(settings.validate().is_ok());
+
+        settings.exclude_images = vec!["pause:3.9".to_string()];
+        assert!(settings.validate().is_err());
+        settings.exclude_images = vec!["registry.k8s.io/Pause:3.9".to_string()];
+        assert!(settings.validate().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_debug_images() -> Result<(), ()> {
+        let settings = |debug_images: serde_json::Value| -> Settings {
+            serde_json::from_value(serde_json::json!({ "debugImages": debug_images })).unwrap()
+        };
+
+        assert!(settings(serde_json::json!({
+            "busybox": "registry.corp/tools/busybox:1.36"
+        }))
+        .validate()
+        .is_ok());
+        assert_eq!(
+            settings(serde_json::json!({"busybox": "busybox:1.36"})).validate(),
+            Err(String::from(
+                "debugImages busybox: busybox:1.36 must be a fully-qualified reference, \
+                 with a registry and a tag or digest, e.g. registry.k8s.io/pause:3.9"
+            ))
+        );
+        assert!(settings(serde_json::json!({
+            "BusyBox": "registry.corp/tools/busybox:1.36"
+        }))
+        .validate()
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_exact_rules() -> Result<(), ()> {
+        let settings = |rules: serde_json::Value| -> Settings {
+            serde_json::from_value(serde_json::json!({ "rules": rules })).unwrap()
+        };
+
+        let valid = settings(serde_json::json!([
+            {"exact": "k8s.gcr.io/pause:3.9", "replace": "registry.corp/infra/pause:3.9-patched"},
+            {"exact": "docker.io/library/nginx@sha256:1234", "replace": "registry.corp/nginx:1.25"}
+        ]));
+        assert!(valid.validate().is_ok());
+        assert!(
+            matches!(&valid.rules[0], Rule::Exact { exact, .. } if exact == "k8s.gcr.io/pause:3.9")
+        );
+        assert!(valid
+            .destination_prefixes()
+            .contains(&"registry.corp/infra/pause".to_string()));
+
+        assert_eq!(
+            settings(serde_json::json!([
+                {"exact": "pause:3.9", "replace": "registry.corp/infra/pause:3.9-patched"}
+            ]))
+            .validate(),
+            Err(String::from(
+                "rule for pause:3.9: pause:3.9 must be a fully-qualified reference, \
+                 with a registry and a tag or digest, e.g. registry.k8s.io/pause:3.9"
+            ))
+        );
+        assert!(settings(serde_json::json!([
+            {"exact": "k8s.gcr.io/pause:3.9", "replace": "registry.corp/infra/pause"}
+        ]))
+        .validate()
+        .is_err());
+        assert!(settings(serde_json::json!([
+            {"exact": "k8s.gcr.io/Pause:3.9", "replace": "registry.corp/infra/pause:3.9"}
+        ]))
+        .validate()
+        .is_err());
+        assert!(settings(serde_json::json!([
+            {"exact": "docker.io/library/nginx:1.25", "replace": "registry.corp/nginx:1.25"},
+            {"exact": "index.docker.io/library/nginx:1.25", "replace": "registry.corp/nginx:1.25"}
+        ]))
+        .validate()
+        .is_err_and(
+            |e| e.contains("rule #2 for index.docker.io/library/nginx:1.25 is shadowed by rule #1")
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn namespace_overrides() -> Result<(), ()> {
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "repos": {
+                "docker.io": "mirror.corp/docker",
+                "quay.io": "mirror.corp/quay"
+            },
+            "rules": [{"source": "ghcr.io", "destination": "mirror.corp/ghcr"}],
+            "namespaceOverrides": {
+                "team-a": {
+                    "repos": {"docker.io": "harbor.corp/team-a"},
+                    "rules": [{"source": "ghcr.io/team-a", "destination": "harbor.corp/team-a-ghcr"}]
+                }
+            }
+        }))
+        .unwrap();
+        assert!(settings.validate().is_ok());
+        assert!(settings.for_namespace("team-b").is_none());
+
+        let team_a = settings.for_namespace("team-a").unwrap();
+        assert_eq!(team_a.repos["docker.io"].prefix(), "harbor.corp/team-a");
+        assert_eq!(team_a.repos["quay.io"].prefix(), "mirror.corp/quay");
+        assert_eq!(team_a.rules.len(), 2);
+        assert!(
+            matches!(&team_a.rules[0], Rule::Prefix { source, .. } if source == "ghcr.io/team-a")
+        );
+
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "namespaceOverrides": {"team-a": {"repos": {"docker.io": "mirror.corp/{repo}"}}}
+        }))
+        .unwrap();
+        assert!(settings
+            .validate()
+            .is_err_and(|e| e.starts_with("namespaceOverrides team-a: ")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn mirror_sets() -> Result<(), ()> {
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "repos": {"docker.io": "mirror.corp/docker"},
+            "mirrorSets": {
+                "edge-mirror": {"repos": {"docker.io": "edge.mirror.corp/docker"}},
+                "backup": {"repos": {"docker.io": "backup.corp/docker"}}
+            }
+        }))
+        .unwrap();
+        assert!(settings.validate().is_ok());
+
+        let edge = settings.for_mirror_set("edge-mirror").unwrap();
+        assert_eq!(edge.repos["docker.io"].prefix(), "edge.mirror.corp/docker");
+        assert_eq!(
+            settings.for_mirror_set("core").err(),
+            Some(String::from(
+                "unknown mirror set core selected by the \
+                 mutate-repos-policy.kubewarden.io/mirror annotation, \
+                 expected one of: backup, edge-mirror"
+            ))
+        );
+
         Ok(())
     }
 }
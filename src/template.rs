@@ -0,0 +1,118 @@
+//! Destinations templated with components of the rewritten image, e.g.
+//! `harbor.corp/{registry}/{image}:{tag}`.
+
+use crate::image::ImageRef;
+
+/// Placeholders available to templated destinations
+const PLACEHOLDERS: &[&str] = &["registry", "image", "tag", "digest"];
+
+/// Returns the value of the placeholder `name` for `image`
+fn value(name: &str, image: &ImageRef) -> Option<String> {
+    match name {
+        "registry" => Some(image.registry().unwrap_or_default()),
+        "image" => Some(image.image.clone()),
+        "tag" => Some(image.tag.clone().unwrap_or_default()),
+        "digest" => Some(image.hash.clone().unwrap_or_default()),
+        _ => None,
+    }
+}
+
+/// Determines if a destination is the template of the whole rewritten image,
+/// rather than of a prefix replacing the matched components
+pub(crate) fn is_image_template(destination: &str) -> bool {
+    destination.contains("{image}")
+}
+
+/// Checks that every placeholder of a destination is known and closed
+pub(crate) fn validate(destination: &str) -> Result<(), String> {
+    let mut rest = destination;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            return Err(format!("unclosed placeholder in {}", destination));
+        };
+        let name = &rest[start + 1..start + len];
+        if !PLACEHOLDERS.contains(&name) {
+            return Err(format!(
+                "unknown placeholder {{{}}} in {}, expected one of {}",
+                name,
+                destination,
+                PLACEHOLDERS.join(", ")
+            ));
+        }
+        rest = &rest[start + len + 1..];
+    }
+    Ok(())
+}
+
+/// Renders a destination with the components of `image`. An empty `{tag}`
+/// or `{digest}` drops the `:` or `@` right before it.
+pub(crate) fn render(destination: &str, image: &ImageRef) -> String {
+    let mut rendered = String::new();
+    let mut rest = destination;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        rendered.push_str(&rest[..start]);
+        let placeholder = &rest[start..=start + len];
+        match value(&placeholder[1..len], image) {
+            Some(value) => {
+                if value.is_empty() && (rendered.ends_with(':') || rendered.ends_with('@')) {
+                    rendered.pop();
+                }
+                rendered.push_str(&value);
+            }
+            None => rendered.push_str(placeholder),
+        }
+        rest = &rest[start + len + 1..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_destination() -> Result<(), ()> {
+        let template = "harbor.corp/{registry}/{image}:{tag}@{digest}";
+        assert_eq!(
+            render(template, &ImageRef::parse("quay.io/foo/bar:v1")),
+            "harbor.corp/quay.io/foo/bar:v1"
+        );
+        assert_eq!(
+            render(template, &ImageRef::parse("alpine@sha256:1234")),
+            "harbor.corp/docker.io/library/alpine@sha256:1234"
+        );
+        assert_eq!(
+            render(
+                "mirror.corp/{registry}",
+                &ImageRef::parse("localhost:5000/app")
+            ),
+            "mirror.corp/localhost:5000"
+        );
+        assert_eq!(
+            render("mirror.corp/flat", &ImageRef::parse("alpine")),
+            "mirror.corp/flat"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_destination() -> Result<(), ()> {
+        assert!(validate("mirror.corp").is_ok());
+        assert!(validate("harbor.corp/{registry}/{image}:{tag}").is_ok());
+        assert_eq!(
+            validate("harbor.corp/{repo}"),
+            Err(String::from(
+                "unknown placeholder {repo} in harbor.corp/{repo}, \
+                 expected one of registry, image, tag, digest"
+            ))
+        );
+        assert!(validate("harbor.corp/{registry").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,226 @@
+use k8s_openapi::api::apps::v1 as apiapps;
+use k8s_openapi::api::batch::v1 as apibatch;
+use k8s_openapi::api::core::v1 as apicore;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use k8s_openapi::Metadata;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// A Kubernetes resource running pods, either directly or through the pod
+/// template embedded in its spec
+pub(crate) trait Workload: Metadata<Ty = ObjectMeta> + DeserializeOwned + Serialize {
+    /// Returns the metadata and the spec of the pods run by the workload, if
+    /// the workload has a spec at all
+    fn pod_mut(&mut self) -> Option<(&mut ObjectMeta, &mut apicore::PodSpec)>;
+
+    /// Returns the metadata of the pods run by the workload, if any
+    fn pod_metadata(&self) -> Option<&ObjectMeta>;
+
+    /// Drops the status of the workload, which is owned by the API server
+    fn clear_status(&mut self);
+}
+
+/// Returns the metadata and the spec of a pod template
+fn template_mut(
+    template: &mut apicore::PodTemplateSpec,
+) -> Option<(&mut ObjectMeta, &mut apicore::PodSpec)> {
+    let spec = template.spec.as_mut()?;
+    Some((template.metadata.get_or_insert_with(Default::default), spec))
+}
+
+/// Returns the images of the containers, of every kind, and of the image
+/// volumes of a pod
+pub(crate) fn images(pod_spec: &apicore::PodSpec) -> Vec<&str> {
+    let containers = pod_spec
+        .containers
+        .iter()
+        .chain(pod_spec.init_containers.iter().flatten())
+        .filter_map(|container| container.image.as_deref());
+    let ephemeral_containers = pod_spec
+        .ephemeral_containers
+        .iter()
+        .flatten()
+        .filter_map(|container| container.image.as_deref());
+    let volumes = pod_spec
+        .volumes
+        .iter()
+        .flatten()
+        .filter_map(|volume| volume.image.as_ref()?.reference.as_deref());
+    containers
+        .chain(ephemeral_containers)
+        .chain(volumes)
+        .collect()
+}
+
+impl Workload for apicore::Pod {
+    fn pod_mut(&mut self) -> Option<(&mut ObjectMeta, &mut apicore::PodSpec)> {
+        Some((&mut self.metadata, self.spec.as_mut()?))
+    }
+
+    fn pod_metadata(&self) -> Option<&ObjectMeta> {
+        Some(&self.metadata)
+    }
+
+    fn clear_status(&mut self) {
+        self.status = None;
+    }
+}
+
+impl Workload for apicore::ReplicationController {
+    fn pod_mut(&mut self) -> Option<(&mut ObjectMeta, &mut apicore::PodSpec)> {
+        template_mut(self.spec.as_mut()?.template.as_mut()?)
+    }
+
+    fn pod_metadata(&self) -> Option<&ObjectMeta> {
+        self.spec.as_ref()?.template.as_ref()?.metadata.as_ref()
+    }
+
+    fn clear_status(&mut self) {
+        self.status = None;
+    }
+}
+
+impl Workload for apiapps::Deployment {
+    fn pod_mut(&mut self) -> Option<(&mut ObjectMeta, &mut apicore::PodSpec)> {
+        template_mut(&mut self.spec.as_mut()?.template)
+    }
+
+    fn pod_metadata(&self) -> Option<&ObjectMeta> {
+        self.spec.as_ref()?.template.metadata.as_ref()
+    }
+
+    fn clear_status(&mut self) {
+        self.status = None;
+    }
+}
+
+impl Workload for apiapps::StatefulSet {
+    fn pod_mut(&mut self) -> Option<(&mut ObjectMeta, &mut apicore::PodSpec)> {
+        template_mut(&mut self.spec.as_mut()?.template)
+    }
+
+    fn pod_metadata(&self) -> Option<&ObjectMeta> {
+        self.spec.as_ref()?.template.metadata.as_ref()
+    }
+
+    fn clear_status(&mut self) {
+        self.status = None;
+    }
+}
+
+impl Workload for apiapps::DaemonSet {
+    fn pod_mut(&mut self) -> Option<(&mut ObjectMeta, &mut apicore::PodSpec)> {
+        template_mut(&mut self.spec.as_mut()?.template)
+    }
+
+    fn pod_metadata(&self) -> Option<&ObjectMeta> {
+        self.spec.as_ref()?.template.metadata.as_ref()
+    }
+
+    fn clear_status(&mut self) {
+        self.status = None;
+    }
+}
+
+impl Workload for apiapps::ReplicaSet {
+    fn pod_mut(&mut self) -> Option<(&mut ObjectMeta, &mut apicore::PodSpec)> {
+        template_mut(self.spec.as_mut()?.template.as_mut()?)
+    }
+
+    fn pod_metadata(&self) -> Option<&ObjectMeta> {
+        self.spec.as_ref()?.template.as_ref()?.metadata.as_ref()
+    }
+
+    fn clear_status(&mut self) {
+        self.status = None;
+    }
+}
+
+impl Workload for apibatch::Job {
+    fn pod_mut(&mut self) -> Option<(&mut ObjectMeta, &mut apicore::PodSpec)> {
+        template_mut(&mut self.spec.as_mut()?.template)
+    }
+
+    fn pod_metadata(&self) -> Option<&ObjectMeta> {
+        self.spec.as_ref()?.template.metadata.as_ref()
+    }
+
+    fn clear_status(&mut self) {
+        self.status = None;
+    }
+}
+
+impl Workload for apibatch::CronJob {
+    fn pod_mut(&mut self) -> Option<(&mut ObjectMeta, &mut apicore::PodSpec)> {
+        let job_spec = self.spec.as_mut()?.job_template.spec.as_mut()?;
+        template_mut(&mut job_spec.template)
+    }
+
+    fn pod_metadata(&self) -> Option<&ObjectMeta> {
+        self.spec
+            .as_ref()?
+            .job_template
+            .spec
+            .as_ref()?
+            .template
+            .metadata
+            .as_ref()
+    }
+
+    fn clear_status(&mut self) {
+        self.status = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cronjob_pod_template() -> Result<(), ()> {
+        let mut cronjob: apibatch::CronJob = serde_json::from_value(serde_json::json!({
+            "metadata": {"name": "backup"},
+            "spec": {
+                "schedule": "0 * * * *",
+                "jobTemplate": {"spec": {"template": {"spec": {
+                    "containers": [{"name": "backup", "image": "alpine:3.17"}]
+                }}}}
+            }
+        }))
+        .unwrap();
+
+        let (metadata, spec) = cronjob.pod_mut().unwrap();
+        assert!(metadata.name.is_none());
+        assert_eq!(spec.containers[0].image.as_deref(), Some("alpine:3.17"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn pod_images() -> Result<(), ()> {
+        let pod: apicore::Pod = serde_json::from_value(serde_json::json!({
+            "metadata": {"name": "app"},
+            "spec": {
+                "containers": [{"name": "app", "image": "nginx:1.25"}],
+                "initContainers": [{"name": "init", "image": "busybox"}],
+                "ephemeralContainers": [{"name": "debugger", "image": "alpine"}],
+                "volumes": [{"name": "model", "image": {"reference": "quay.io/foo/model:v1"}}]
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(
+            images(pod.spec.as_ref().unwrap()),
+            vec!["nginx:1.25", "busybox", "alpine", "quay.io/foo/model:v1"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn workload_without_spec() -> Result<(), ()> {
+        let mut deployment = apiapps::Deployment::default();
+        assert!(deployment.pod_mut().is_none());
+
+        Ok(())
+    }
+}